@@ -1,98 +1,474 @@
 use pam_sys::{
-    raw::{pam_start, pam_authenticate, pam_end, pam_open_session, pam_set_item},
+    raw::{pam_start, pam_authenticate, pam_acct_mgmt, pam_chauthtok, pam_setcred, pam_end, pam_open_session, pam_close_session, pam_set_item, pam_getenvlist, pam_strerror},
     types::{PamHandle, PamMessage, PamResponse, PamConversation},
 };
-use std::{ffi::{CStr, CString}, ptr, os::raw::{c_int, c_void}};
+use serde::Deserialize;
+
+use crate::secret::Secret;
+use crate::syslog;
+use std::{error::Error, ffi::{CStr, CString}, fmt, mem::size_of, path::Path, ptr, os::raw::{c_char, c_int, c_void}};
 
 // Define PAM constants
 const PAM_SUCCESS: c_int = 0;
+const PAM_OPEN_ERR: c_int = 1;
+const PAM_SYMBOL_ERR: c_int = 2;
+const PAM_SERVICE_ERR: c_int = 3;
+const PAM_BUF_ERR: c_int = 5;
+const PAM_PERM_DENIED: c_int = 6;
+const PAM_AUTH_ERR: c_int = 7;
+const PAM_CRED_INSUFFICIENT: c_int = 8;
+const PAM_AUTHINFO_UNAVAIL: c_int = 9;
+const PAM_USER_UNKNOWN: c_int = 10;
+const PAM_MAXTRIES: c_int = 11;
+const PAM_NEW_AUTHTOK_REQD: c_int = 12;
+const PAM_ACCT_EXPIRED: c_int = 13;
+const PAM_SESSION_ERR: c_int = 14;
+const PAM_CRED_UNAVAIL: c_int = 15;
+const PAM_CRED_EXPIRED: c_int = 16;
+const PAM_CRED_ERR: c_int = 17;
+const PAM_CONV_ERR: c_int = 19;
+const PAM_AUTHTOK_ERR: c_int = 20;
+const PAM_TRY_AGAIN: c_int = 24;
+const PAM_ABORT: c_int = 26;
+const PAM_AUTHTOK_EXPIRED: c_int = 27;
+const PAM_MODULE_UNKNOWN: c_int = 28;
 const PAM_PROMPT_ECHO_OFF: c_int = 1;
+const PAM_PROMPT_ECHO_ON: c_int = 2;
+const PAM_ERROR_MSG: c_int = 3;
+const PAM_TEXT_INFO: c_int = 4;
 pub const PAM_TTY: c_int = 3;
-extern "C" fn conversation(
+
+// Style of a message sent by a PAM module
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageStyle {
+    PromptEchoOff,
+    PromptEchoOn,
+    ErrorMsg,
+    TextInfo,
+}
+
+impl MessageStyle {
+    fn from_raw(msg_style: c_int) -> Option<MessageStyle> {
+        match msg_style {
+            PAM_PROMPT_ECHO_OFF => Some(MessageStyle::PromptEchoOff),
+            PAM_PROMPT_ECHO_ON => Some(MessageStyle::PromptEchoOn),
+            PAM_ERROR_MSG => Some(MessageStyle::ErrorMsg),
+            PAM_TEXT_INFO => Some(MessageStyle::TextInfo),
+            _ => None,
+        }
+    }
+
+    pub fn is_prompt(&self) -> bool {
+        matches!(self, MessageStyle::PromptEchoOff | MessageStyle::PromptEchoOn)
+    }
+}
+
+// Front end that answers the messages of a PAM conversation (e.g. the greeter)
+pub trait Conversation {
+    // Returns the answer to a prompt, None aborts the conversation.
+    // The returned value is ignored for error and info messages.
+    fn converse(&mut self, style: MessageStyle, message: &str) -> Option<Secret>;
+}
+
+// Wipe and free the first `count` responses and free the response array itself.
+// The responses may already hold passwords copied out of their locked buffers.
+unsafe fn free_responses(responses: *mut PamResponse, count: usize) {
+    for i in 0..count {
+        let response = &mut *responses.add(i);
+        if !response.resp.is_null() {
+            libc::explicit_bzero(response.resp as *mut c_void, libc::strlen(response.resp));
+            libc::free(response.resp as *mut c_void);
+        }
+    }
+    libc::free(responses as *mut c_void);
+}
+
+extern "C" fn conversation_fn(
     num_msg: c_int,
     msg: *mut *mut PamMessage,
     resp: *mut *mut PamResponse,
     appdata_ptr: *mut c_void,
 ) -> c_int {
-    if num_msg != 1 || msg.is_null() || appdata_ptr.is_null() {
-        return -1;
+    if num_msg <= 0 || msg.is_null() || resp.is_null() || appdata_ptr.is_null() {
+        return PAM_CONV_ERR;
     }
 
     unsafe {
-        let msg_ptr = *msg;
-        if msg_ptr.is_null() {
-            return -1;
+        // appdata_ptr points to the conversation owned by the PamSession
+        let handler = &mut *(appdata_ptr as *mut Box<dyn Conversation>);
+
+        // PAM frees the responses with free(), so they have to come from the C allocator
+        let responses = libc::calloc(num_msg as usize, size_of::<PamResponse>()) as *mut PamResponse;
+        if responses.is_null() {
+            return PAM_BUF_ERR;
         }
 
-        let pam_message = &*msg_ptr;
+        for i in 0..num_msg as usize {
+            // Linux-PAM passes an array of message pointers
+            let msg_ptr = *msg.add(i);
+            if msg_ptr.is_null() {
+                free_responses(responses, i);
+                return PAM_CONV_ERR;
+            }
+            let pam_message = &*msg_ptr;
 
-        if pam_message.msg_style != PAM_PROMPT_ECHO_OFF {
-            return -1;
-        }
+            let Some(style) = MessageStyle::from_raw(pam_message.msg_style) else {
+                free_responses(responses, i);
+                return PAM_CONV_ERR;
+            };
+            let text = if pam_message.msg.is_null() {
+                String::new()
+            } else {
+                CStr::from_ptr(pam_message.msg).to_string_lossy().into_owned()
+            };
 
-        // The response to the prompt (password)
-        let password = CStr::from_ptr(appdata_ptr as *const i8);
+            let Some(answer) = handler.converse(style, &text) else {
+                free_responses(responses, i);
+                return PAM_CONV_ERR;
+            };
 
-        // Allocate PamResponse on the heap directly using Box
-        let pam_response = Box::new(PamResponse {
-            resp: CString::new(password.to_string_lossy().into_owned()).unwrap().into_raw(),
-            resp_retcode: 0,
-        });
+            // Only prompts get an answer, the response of a message stays NULL.
+            // The answer is copied straight from the locked buffer into memory owned by PAM.
+            if style.is_prompt() {
+                if answer.as_bytes().contains(&0) {
+                    free_responses(responses, i);
+                    return PAM_CONV_ERR;
+                }
+                let resp_ptr = answer.to_pam_response();
+                if resp_ptr.is_null() {
+                    free_responses(responses, i);
+                    return PAM_BUF_ERR;
+                }
+                (*responses.add(i)).resp = resp_ptr;
+            }
+        }
 
-        // Convert the Box into a raw pointer and give it to PAM
-        *resp = Box::into_raw(pam_response);
+        *resp = responses;
     }
     PAM_SUCCESS
 }
 
-pub fn auth_user(username: &str, password: &str, tty_path: &str) -> bool {
-    let service_name = CString::new("login").unwrap();
-    let c_username = CString::new(username).unwrap();
-    let c_password = CString::new(password).unwrap();
+// Reason why a login failed
+#[derive(Debug)]
+pub enum AuthError {
+    // No configuration for the PAM service in /etc/pam.d
+    ServiceNotFound(String),
+    // Wrong password or other credentials
+    AuthFailed,
+    UserUnknown,
+    MaxTries,
+    // Account is locked or not allowed to log in
+    PermissionDenied,
+    AccountExpired,
+    PasswordExpired,
+    PasswordChangeFailed,
+    CredentialsFailed,
+    SessionFailed,
+    // Authentication service (e.g. LDAP, Kerberos) cannot be reached
+    ServiceUnavailable,
+    // Broken PAM stack (e.g. missing module)
+    Misconfigured,
+    ConversationAborted,
+    // Too many failed logins, seconds until the next attempt is allowed
+    Throttled(u64),
+    // The session rules don't allow the user to start the session
+    SessionNotAllowed,
+    Other(c_int),
+}
 
-    // Correctly declare pam_handle as *mut *const PamHandle
-    let mut pam_handle: *mut PamHandle = ptr::null_mut();
-    let pam_conv = PamConversation {
-        conv: Some(conversation),
-        data_ptr: c_password.as_ptr() as *mut c_void,
-    };
+impl AuthError {
+    fn from_pam(code: c_int) -> AuthError {
+        match code {
+            PAM_AUTH_ERR => AuthError::AuthFailed,
+            PAM_USER_UNKNOWN => AuthError::UserUnknown,
+            PAM_MAXTRIES => AuthError::MaxTries,
+            PAM_PERM_DENIED => AuthError::PermissionDenied,
+            PAM_ACCT_EXPIRED => AuthError::AccountExpired,
+            PAM_NEW_AUTHTOK_REQD | PAM_AUTHTOK_EXPIRED => AuthError::PasswordExpired,
+            PAM_AUTHTOK_ERR => AuthError::PasswordChangeFailed,
+            PAM_CRED_INSUFFICIENT | PAM_CRED_UNAVAIL | PAM_CRED_EXPIRED | PAM_CRED_ERR => AuthError::CredentialsFailed,
+            PAM_SESSION_ERR => AuthError::SessionFailed,
+            PAM_AUTHINFO_UNAVAIL | PAM_TRY_AGAIN => AuthError::ServiceUnavailable,
+            PAM_OPEN_ERR | PAM_SYMBOL_ERR | PAM_SERVICE_ERR | PAM_MODULE_UNKNOWN => AuthError::Misconfigured,
+            PAM_CONV_ERR | PAM_ABORT => AuthError::ConversationAborted,
+            _ => AuthError::Other(code),
+        }
+    }
 
-    // Call pam_start with pam_handle as *mut *const PamHandle
-    let result_start_pam = unsafe {
-        pam_start(service_name.as_ptr(), c_username.as_ptr(), &pam_conv, &mut pam_handle as *mut *mut PamHandle as *mut *const PamHandle)
-    };
-    if result_start_pam != PAM_SUCCESS {
+    // Failures caused by wrong credentials, these count for the login throttle
+    pub fn is_credential_failure(&self) -> bool {
+        matches!(self, AuthError::AuthFailed | AuthError::UserUnknown | AuthError::MaxTries)
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::ServiceNotFound(service) => write!(f, "Login not possible, PAM service '{}' not found in /etc/pam.d.", service),
+            AuthError::AuthFailed => write!(f, "Authentication failed."),
+            AuthError::UserUnknown => write!(f, "Unknown user."),
+            AuthError::MaxTries => write!(f, "Too many failed attempts."),
+            AuthError::PermissionDenied => write!(f, "Access denied, the account may be locked."),
+            AuthError::AccountExpired => write!(f, "The account has expired."),
+            AuthError::PasswordExpired => write!(f, "The password has expired."),
+            AuthError::PasswordChangeFailed => write!(f, "Changing the password failed."),
+            AuthError::CredentialsFailed => write!(f, "Failed to set up the credentials."),
+            AuthError::SessionFailed => write!(f, "Failed to open the session."),
+            AuthError::ServiceUnavailable => write!(f, "Authentication service unavailable."),
+            AuthError::Misconfigured => write!(f, "PAM is misconfigured, contact the administrator."),
+            AuthError::ConversationAborted => write!(f, "Login aborted."),
+            AuthError::Throttled(remaining) => write!(f, "Too many failed logins, try again in {} s.", remaining),
+            AuthError::SessionNotAllowed => write!(f, "You are not allowed to start this session."),
+            AuthError::Other(code) => write!(f, "Login failed (PAM error {}).", code),
+        }
+    }
+}
+
+impl Error for AuthError {}
+
+// Flags for pam_setcred and pam_chauthtok
+const PAM_ESTABLISH_CRED: c_int = 0x2;
+const PAM_DELETE_CRED: c_int = 0x4;
+const PAM_CHANGE_EXPIRED_AUTHTOK: c_int = 0x20;
+
+// One login transaction of an authentication backend
+pub trait AuthSession {
+    // Send a message of nclogin itself through the conversation
+    fn notify(&mut self, style: MessageStyle, message: &str);
+    // Verify the user's credentials, asking for them through the conversation
+    fn authenticate(&mut self) -> Result<(), AuthError>;
+    // Check if the account is valid, Err(PasswordExpired) if the password must be changed
+    fn account_check(&mut self) -> Result<(), AuthError>;
+    fn change_expired_authtok(&mut self) -> Result<(), AuthError>;
+    // Establish the credentials and open the session
+    fn open_session(&mut self) -> Result<(), AuthError>;
+    // Environment set up for the session as name / value pairs
+    fn env_list(&mut self) -> Vec<(String, String)>;
+    // Close the session and delete the credentials, does nothing if no session is open
+    fn close_session(&mut self);
+}
+
+// Authentication backend (PAM or the mock backend)
+pub trait Authenticator {
+    fn start<'a>(&self, service: &str, username: &str, tty_path: &str, conv: Box<dyn Conversation + 'a>) -> Result<Box<dyn AuthSession + 'a>, AuthError>;
+}
+
+// Directories Linux-PAM reads service configurations from
+const PAM_CONFIG_DIRS: [&str; 2] = ["/etc/pam.d", "/usr/lib/pam.d"];
+
+// Check if a configuration for the PAM service exists, otherwise PAM falls back to the "other" service
+pub fn pam_service_exists(service: &str) -> bool {
+    if service.is_empty() || service.contains('/') {
         return false;
     }
+    PAM_CONFIG_DIRS.iter().any(|dir| Path::new(dir).join(service).is_file())
+}
 
-    let auth_result = unsafe { pam_authenticate(pam_handle, 0) };
-    if auth_result == PAM_SUCCESS {
-        unsafe {
-            let tty = CString::new(tty_path).unwrap();  // Specify the terminal (tty1)
-            let tty_ptr: *const c_void = tty.as_ptr() as *const c_void;  // Cast to *const c_void
-            pam_set_item(pam_handle, PAM_TTY, tty_ptr);
+// Authenticator using the system's PAM stack
+pub struct PamAuthenticator;
+
+impl Authenticator for PamAuthenticator {
+    fn start<'a>(&self, service: &str, username: &str, tty_path: &str, conv: Box<dyn Conversation + 'a>) -> Result<Box<dyn AuthSession + 'a>, AuthError> {
+        if !pam_service_exists(service) {
+            syslog::error(&format!("PAM service {} not found in {}", service, PAM_CONFIG_DIRS.join(", ")));
+            return Err(AuthError::ServiceNotFound(service.to_string()));
+        }
+        let mut pam_session = PamSession::start(service, username, conv)?;
+
+        // Modules like pam_securetty need the tty during authentication
+        pam_session.set_tty(tty_path)?;
+        Ok(Box::new(pam_session))
+    }
+}
 
-            pam_open_session(pam_handle, 0);
+// Owned PAM transaction, closes the session and ends the transaction when dropped
+pub struct PamSession<'a> {
+    pam_handle: *mut PamHandle,
+    username: String,
+    // PAM keeps a pointer to the conversation, so it needs a fixed address for the whole transaction
+    conversation: Box<Box<dyn Conversation + 'a>>,
+    last_result: c_int,
+    credentials_established: bool,
+    session_opened: bool,
+}
+
+impl<'a> PamSession<'a> {
+    pub fn start(service: &str, username: &str, conv: Box<dyn Conversation + 'a>) -> Result<PamSession<'a>, AuthError> {
+        let service_name = CString::new(service).map_err(|_| AuthError::Other(PAM_BUF_ERR))?;
+        let c_username = CString::new(username).map_err(|_| AuthError::UserUnknown)?;
+
+        let mut conversation = Box::new(conv);
+        let pam_conv = PamConversation {
+            conv: Some(conversation_fn),
+            data_ptr: &mut *conversation as *mut Box<dyn Conversation + 'a> as *mut c_void,
+        };
+
+        // Correctly declare pam_handle as *mut *const PamHandle
+        let mut pam_handle: *mut PamHandle = ptr::null_mut();
+        // pam_start copies the pam_conv struct, only the data_ptr has to stay valid
+        let result = unsafe {
+            pam_start(service_name.as_ptr(), c_username.as_ptr(), &pam_conv, &mut pam_handle as *mut *mut PamHandle as *mut *const PamHandle)
+        };
+        if result != PAM_SUCCESS {
+            // Without a handle there is no pam_strerror
+            syslog::error(&format!("PAM start of service {} for {} failed (code {})", service, username, result));
+            return Err(AuthError::from_pam(result));
+        }
+
+        Ok(PamSession {
+            pam_handle,
+            username: username.to_string(),
+            conversation,
+            last_result: PAM_SUCCESS,
+            credentials_established: false,
+            session_opened: false,
+        })
+    }
+
+    // Remember the result for pam_end, log failures with the raw PAM code and turn it into a Result
+    fn check(&mut self, stage: &str, result: c_int) -> Result<(), AuthError> {
+        self.last_result = result;
+        if result == PAM_SUCCESS {
+            return Ok(());
+        }
+        let reason = unsafe {
+            let reason_ptr = pam_strerror(self.pam_handle, result);
+            if reason_ptr.is_null() {
+                String::from("unknown error")
+            } else {
+                CStr::from_ptr(reason_ptr).to_string_lossy().into_owned()
+            }
         };
+        syslog::error(&format!("PAM {} for {} failed: {} (code {})", stage, self.username, reason, result));
+        Err(AuthError::from_pam(result))
     }
-    let end_result = unsafe { pam_end(pam_handle, auth_result) };
 
-    if auth_result == PAM_SUCCESS && end_result == PAM_SUCCESS {
-        true
-    } else {
-        false
+    pub fn set_tty(&mut self, tty_path: &str) -> Result<(), AuthError> {
+        let tty = CString::new(tty_path).map_err(|_| AuthError::Other(PAM_BUF_ERR))?;
+        let result = unsafe { pam_set_item(self.pam_handle, PAM_TTY, tty.as_ptr() as *const c_void) };
+        self.check("set_item", result)
+    }
+
+    fn establish_credentials(&mut self) -> Result<(), AuthError> {
+        let result = unsafe { pam_setcred(self.pam_handle, PAM_ESTABLISH_CRED) };
+        self.check("setcred", result)?;
+        self.credentials_established = true;
+        Ok(())
     }
 }
 
-#[allow(dead_code)]
-fn main() {
-    // Get username and password from user input
-    let username = "user"; // Replace with actual username input
-    let password = "pw";     // Replace with the actual password
+impl AuthSession for PamSession<'_> {
+    fn notify(&mut self, style: MessageStyle, message: &str) {
+        self.conversation.converse(style, message);
+    }
+
+    fn authenticate(&mut self) -> Result<(), AuthError> {
+        let result = unsafe { pam_authenticate(self.pam_handle, 0) };
+        self.check("authenticate", result)
+    }
 
-    if auth_user(username, password, "/dev/tty5") {
-        println!("Authentication successful!");
-    } else {
-        println!("Authentication failed.");
+    fn account_check(&mut self) -> Result<(), AuthError> {
+        let result = unsafe { pam_acct_mgmt(self.pam_handle, 0) };
+        self.check("acct_mgmt", result)
     }
+
+    // Let PAM ask for the old and the new password through the conversation
+    fn change_expired_authtok(&mut self) -> Result<(), AuthError> {
+        let result = unsafe { pam_chauthtok(self.pam_handle, PAM_CHANGE_EXPIRED_AUTHTOK) };
+        self.check("chauthtok", result).map_err(|e| match e {
+            AuthError::ConversationAborted => e,
+            _ => AuthError::PasswordChangeFailed,
+        })
+    }
+
+    fn open_session(&mut self) -> Result<(), AuthError> {
+        self.establish_credentials()?;
+        let result = unsafe { pam_open_session(self.pam_handle, 0) };
+        self.check("open_session", result)?;
+        self.session_opened = true;
+        Ok(())
+    }
+
+    // Get the environment set by PAM modules (e.g. pam_env, pam_systemd)
+    fn env_list(&mut self) -> Vec<(String, String)> {
+        let mut env = Vec::new();
+        unsafe {
+            // pam_getenvlist returns a copy of the environment, that has to be freed by the caller
+            let list = pam_getenvlist(self.pam_handle) as *mut *mut c_char;
+            if list.is_null() {
+                return env;
+            }
+            let mut i = 0;
+            while !(*list.add(i)).is_null() {
+                let entry = *list.add(i);
+                let entry_str = CStr::from_ptr(entry).to_string_lossy().into_owned();
+                if let Some((name, value)) = entry_str.split_once('=') {
+                    env.push((name.to_string(), value.to_string()));
+                }
+                libc::free(entry as *mut c_void);
+                i += 1;
+            }
+            libc::free(list as *mut c_void);
+        }
+        env
+    }
+
+    fn close_session(&mut self) {
+        unsafe {
+            if self.session_opened {
+                let result = pam_close_session(self.pam_handle, 0);
+                if result != PAM_SUCCESS {
+                    syslog::error(&format!("Failed to close PAM session: {}", result));
+                }
+                self.session_opened = false;
+            }
+            if self.credentials_established {
+                let result = pam_setcred(self.pam_handle, PAM_DELETE_CRED);
+                if result != PAM_SUCCESS {
+                    syslog::error(&format!("Failed to delete PAM credentials: {}", result));
+                }
+                self.credentials_established = false;
+            }
+        }
+    }
+}
+
+impl Drop for PamSession<'_> {
+    fn drop(&mut self) {
+        self.close_session();
+        unsafe {
+            pam_end(self.pam_handle, self.last_result);
+        }
+    }
+}
+
+// Authenticate the user and open a session that lives until close_session is called or it is dropped
+pub fn auth_user<'a>(authenticator: &dyn Authenticator, service: &str, username: &str, tty_path: &str, conv: Box<dyn Conversation + 'a>) -> Result<Box<dyn AuthSession + 'a>, AuthError> {
+    let mut auth_session = authenticator.start(service, username, tty_path, conv)?;
+
+    auth_session.authenticate()?;
+    match auth_session.account_check() {
+        Ok(()) => {}
+        Err(AuthError::PasswordExpired) => {
+            auth_session.notify(MessageStyle::TextInfo, "Your password has expired, please choose a new one.");
+            auth_session.change_expired_authtok()?;
+        }
+        Err(e) => return Err(e),
+    }
+    auth_session.open_session()?;
+
+    Ok(auth_session)
+}
+
+// Open a session without authenticating the user, the auth stack of the service is only run by
+// pam_setcred and has to permit without a conversation (pam_permit).
+// The account is still checked, an expired password can't be changed without the user and fails the login.
+pub fn autologin_user<'a>(authenticator: &dyn Authenticator, service: &str, username: &str, tty_path: &str, conv: Box<dyn Conversation + 'a>) -> Result<Box<dyn AuthSession + 'a>, AuthError> {
+    let mut auth_session = authenticator.start(service, username, tty_path, conv)?;
+
+    auth_session.account_check()?;
+    auth_session.open_session()?;
+
+    Ok(auth_session)
 }
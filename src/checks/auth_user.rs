@@ -0,0 +1,49 @@
+// Log in through PAM with the conversation on stdin / stdout: cargo run --bin auth_user [user] [service]
+use std::{env, io::{self, Read, Write}};
+
+use nclogin::auth_user::{auth_user, Conversation, MessageStyle, PamAuthenticator};
+use nclogin::secret::Secret;
+
+// Conversation on stdin / stdout for testing
+struct StdinConversation;
+
+impl Conversation for StdinConversation {
+    fn converse(&mut self, style: MessageStyle, message: &str) -> Option<Secret> {
+        match style {
+            MessageStyle::ErrorMsg => eprintln!("{}", message),
+            MessageStyle::TextInfo => println!("{}", message),
+            MessageStyle::PromptEchoOff | MessageStyle::PromptEchoOn => {
+                print!("{}", message);
+                io::stdout().flush().ok()?;
+            }
+        }
+        let mut answer = Secret::new();
+        if !style.is_prompt() {
+            return Some(answer);
+        }
+        // Read byte by byte into the locked buffer, a line buffer would leave a copy of the password
+        for byte in io::stdin().lock().bytes() {
+            match byte.ok()? {
+                b'\n' => break,
+                byte => {
+                    answer.push_bytes(&[byte]);
+                }
+            }
+        }
+        Some(answer)
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let username = args.next().unwrap_or_else(|| "user".to_string());
+    let service = args.next().unwrap_or_else(|| "login".to_string());
+
+    match auth_user(&PamAuthenticator, &service, &username, "/dev/tty5", Box::new(StdinConversation)) {
+        Ok(mut auth_session) => {
+            println!("Authentication successful!");
+            auth_session.close_session();
+        }
+        Err(e) => println!("{}", e),
+    }
+}
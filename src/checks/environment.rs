@@ -0,0 +1,56 @@
+// Spawns id as a user the way sessions are started and compares its groups with NSS: cargo run --bin environment [user]
+// Switching the user needs root, the check is skipped otherwise.
+use std::{env, ffi::CString, process::Command};
+
+use nclogin::environment;
+use nclogin::user::User;
+
+// Output of id run as the user through the session path
+fn id_as_user(user: &User, option: &str) -> String {
+    let mut cmd = Command::new("id");
+    cmd.arg(option);
+    environment::run_as_user(&mut cmd, user);
+    let output = cmd.output().expect("id can be started");
+    assert!(output.status.success(), "id {} failed: {}", option, String::from_utf8_lossy(&output.stderr));
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+fn main() {
+    if !nix::unistd::geteuid().is_root() {
+        println!("Skipped the session environment check, it needs root");
+        return;
+    }
+
+    let user_name = env::args().nth(1).unwrap_or_else(|| "nobody".to_string());
+    let entry = nix::unistd::User::from_name(&user_name)
+        .expect("passwd can be read")
+        .unwrap_or_else(|| panic!("{} not found", user_name));
+    let user = User {
+        name: entry.name.clone(),
+        uid: entry.uid.as_raw() as usize,
+        gid: entry.gid.as_raw() as usize,
+        gecos: String::new(),
+        homedir: entry.dir.display().to_string(),
+        shell: entry.shell.display().to_string(),
+    };
+
+    let mut expected: Vec<u32> = nix::unistd::getgrouplist(&CString::new(user_name.as_str()).unwrap(), entry.gid)
+        .expect("groups can be read")
+        .into_iter()
+        .map(|gid| gid.as_raw())
+        .collect();
+    expected.sort_unstable();
+    expected.dedup();
+
+    let mut groups: Vec<u32> = id_as_user(&user, "-G")
+        .split_whitespace()
+        .map(|gid| gid.parse().expect("numeric group id"))
+        .collect();
+    groups.sort_unstable();
+    groups.dedup();
+
+    assert_eq!(id_as_user(&user, "-u"), entry.uid.to_string());
+    assert_eq!(id_as_user(&user, "-g"), entry.gid.to_string());
+    assert_eq!(groups, expected, "groups of {} differ from getgrouplist", user_name);
+    println!("Session environment checks passed for {}: groups {:?}", user_name, groups);
+}
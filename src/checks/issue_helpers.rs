@@ -0,0 +1,40 @@
+// Print the system information of the issue file and show it with ncurses: cargo run --bin issue_helpers [issue file]
+use std::{env, thread::sleep, time::Duration};
+use gettextrs::{setlocale, LocaleCategory};
+use ncursesw::{endwin, getch, initscr, refresh};
+
+use nclogin::issue_helpers::*;
+
+fn show_issue_file(path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    setlocale(LocaleCategory::LcAll, "");
+    let lines = read_issue_file(path, "/var/run/utmp");
+
+    // Initialize ncurses
+    initscr()?;
+    draw_on_screen(lines, 15, 5);
+    refresh()?;
+
+    // Wait for the user to press a key before exiting
+    getch()?;
+    sleep(Duration::from_millis(1000));
+    // End ncurses
+    endwin()?;
+
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("Testing Rust System Information Functions:");
+    println!("Host Name: {}", get_host_name());
+    println!("OS Name: {}", get_os_name());
+    println!("OS Version: {}", get_os_version());
+    println!("Architecture: {}", get_architecture());
+    println!("TTY Name: {}", get_tty_name());
+    println!("Current Time: {}", get_current_time());
+    println!("Current Date: {}", get_current_date());
+    println!("Logged in Users: {}", get_logged_in_users("/var/run/utmp"));
+    println!("Uptime: {}", get_uptime());
+
+    let path = env::args().nth(1).unwrap_or_else(|| "/etc/nclogin/issue".to_string());
+    show_issue_file(&path)
+}
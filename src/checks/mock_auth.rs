@@ -0,0 +1,77 @@
+// Runs the login flow against the mock users of the demo fixture: cargo run --bin mock_auth [fixture]
+use std::{cell::RefCell, env, rc::Rc};
+
+use nclogin::auth_user::{auth_user, AuthError, Conversation, MessageStyle};
+use nclogin::mock_auth::MockAuthenticator;
+use nclogin::secret::Secret;
+
+// Answers prompts from a script and records every message it was sent
+struct ScriptedConversation {
+    answers: Vec<(&'static str, &'static str)>,
+    seen: Rc<RefCell<Vec<String>>>,
+}
+
+impl Conversation for ScriptedConversation {
+    fn converse(&mut self, style: MessageStyle, message: &str) -> Option<Secret> {
+        self.seen.borrow_mut().push(message.to_string());
+        match style {
+            MessageStyle::PromptEchoOff | MessageStyle::PromptEchoOn => self.answers.iter()
+                .find(|(prompt, _)| *prompt == message)
+                .map(|(_, answer)| Secret::copy_from_str(answer)),
+            MessageStyle::ErrorMsg | MessageStyle::TextInfo => Some(Secret::new()),
+        }
+    }
+}
+// Log in with the scripted answers, returns the result and the messages the user saw
+fn login(authenticator: &MockAuthenticator, username: &str, answers: &[(&'static str, &'static str)]) -> (Result<(), AuthError>, Vec<String>) {
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let conversation = ScriptedConversation { answers: answers.to_vec(), seen: Rc::clone(&seen) };
+    let result = auth_user(authenticator, "login", username, "/dev/tty9", Box::new(conversation))
+        .map(|mut auth_session| auth_session.close_session());
+    let seen = seen.borrow().clone();
+    (result, seen)
+}
+
+fn main() {
+    let fixture = env::args().nth(1).unwrap_or_else(|| concat!(env!("CARGO_MANIFEST_DIR"), "/config/demo.toml").to_string());
+    let authenticator = MockAuthenticator::from_file(&fixture).expect("readable mock fixture");
+
+    let (result, _) = login(&authenticator, "alice", &[("Password: ", "alice")]);
+    assert!(result.is_ok(), "alice: {:?}", result);
+
+    let (result, _) = login(&authenticator, "alice", &[("Password: ", "wrong")]);
+    assert!(matches!(result, Err(AuthError::AuthFailed)), "alice with a wrong password: {:?}", result);
+
+    let (result, seen) = login(&authenticator, "carol", &[("Password: ", "carol"), ("Verification code: ", "123456")]);
+    assert!(result.is_ok(), "carol: {:?}", result);
+    assert!(seen.iter().any(|message| message == "Verification code: "), "carol wasn't asked for the code: {:?}", seen);
+
+    let (result, _) = login(&authenticator, "carol", &[("Password: ", "carol"), ("Verification code: ", "000000")]);
+    assert!(matches!(result, Err(AuthError::AuthFailed)), "carol with a wrong code: {:?}", result);
+
+    let (result, seen) = login(&authenticator, "bob", &[
+        ("Password: ", "bob"),
+        ("Current password: ", "bob"),
+        ("New password: ", "bob-new"),
+        ("Retype new password: ", "bob-new"),
+    ]);
+    assert!(result.is_ok(), "bob: {:?}", result);
+    assert!(seen.iter().any(|message| message.starts_with("Warning: your password will expire")), "bob wasn't warned: {:?}", seen);
+    assert!(seen.iter().any(|message| message == "New password: "), "bob wasn't asked for a new password: {:?}", seen);
+
+    let (result, _) = login(&authenticator, "bob", &[
+        ("Password: ", "bob"),
+        ("Current password: ", "bob"),
+        ("New password: ", "bob-new"),
+        ("Retype new password: ", "typo"),
+    ]);
+    assert!(matches!(result, Err(AuthError::PasswordChangeFailed)), "bob with a mistyped new password: {:?}", result);
+
+    let (result, _) = login(&authenticator, "dave", &[("Password: ", "dave")]);
+    assert!(matches!(result, Err(AuthError::PermissionDenied)), "dave: {:?}", result);
+
+    let (result, _) = login(&authenticator, "mallory", &[("Password: ", "mallory")]);
+    assert!(matches!(result, Err(AuthError::UserUnknown)), "unknown user: {:?}", result);
+
+    println!("Mock login checks passed");
+}
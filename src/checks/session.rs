@@ -0,0 +1,24 @@
+// Print the sessions found with the default dirs or those of a config file: cargo run --bin session [config file]
+use std::env;
+
+use nclogin::session;
+use nclogin::settings::{self, Settings};
+
+fn main() {
+    let config = match env::args().nth(1) {
+        Some(config_path) => settings::parse_settings(&config_path),
+        None => Settings::default(),
+    };
+
+    let (sessions, diagnostics) = session::get_sessions(
+        &config.login_behaviour.session_file,
+        &config.login_behaviour.shells_file,
+        &config.login_behaviour.x11_session_dirs(),
+        &config.login_behaviour.wayland_session_dirs());
+    for session in &sessions {
+        println!("{:?}", session);
+    }
+    for diagnostic in &diagnostics {
+        println!("skipped {}", diagnostic);
+    }
+}
@@ -1,7 +1,29 @@
 use crate::session::Session;
+use crate::state_lock::{self, StateLock};
+use crate::syslog;
 use crate::user::User;
-use std::{fs::File, io, io::{BufRead,Write}};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs::{self, File}, io, io::BufRead, path::Path};
 
+// Last selections, kept in a TOML state file:
+// [last_session]
+// alice = "wayland/sway"
+// [last_user]
+// tty1 = "alice"
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DefaultSelection {
+    // Id of the last session per user name
+    #[serde(default)]
+    pub last_session: HashMap<String, String>,
+    // Last user per tty name
+    #[serde(default)]
+    pub last_user: HashMap<String, String>,
+    // User of the default file of older versions, preselected on ttys without a last user
+    #[serde(skip)]
+    legacy_user: Option<String>,
+}
+
+// Single user and session of the default file of older versions
 fn read_last_usage(file_path: &str) -> io::Result<(String, String)> {
     let file = File::open(file_path)?;
     let reader = io::BufReader::new(file);
@@ -17,27 +39,59 @@ fn read_last_usage(file_path: &str) -> io::Result<(String, String)> {
     Ok((username, session))
 }
 
-pub fn get_default_indices(file_path: &str, users: &Vec<User>, sessions: &Vec<Session>) -> io::Result< (usize, usize)>  {
-    let(default_username, default_session) = read_last_usage(file_path)?;
+impl DefaultSelection {
+    // Read the state file, if it doesn't exist yet the default file of older versions is used
+    // Writers replace the file at once, so reading it needs no lock
+    pub fn load(state_file: &str, legacy_file: &str) -> DefaultSelection {
+        match fs::read_to_string(state_file) {
+            Ok(content) => match toml::from_str(&content) {
+                Ok(selection) => return selection,
+                Err(e) => syslog::error(&format!("Error reading {}: {}", state_file, e)),
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => syslog::error(&format!("Error reading {}: {}", state_file, e)),
+        }
 
-    let mut default_user_index : usize =0;
-    let mut default_session_index :usize =0;
+        let mut selection = DefaultSelection::default();
+        if let Ok((username, session)) = read_last_usage(legacy_file) {
+            selection.last_session.insert(username.clone(), session);
+            selection.legacy_user = Some(username);
+        }
+        selection
+    }
 
-    // Find index of the last used user
-    if let Some(index) = users.iter().position(|user| user.name == default_username) {
-        default_user_index = index;
+    // Read, change and write the state file under an exclusive lock, the helpers of other ttys write the same file
+    pub fn update(state_file: &str, legacy_file: &str, change: impl FnOnce(&mut DefaultSelection)) -> io::Result<()> {
+        let _lock = StateLock::acquire(&Path::new(state_file).with_extension("lock"), true)?;
+        let mut selection = DefaultSelection::load(state_file, legacy_file);
+        change(&mut selection);
+        selection.save(state_file)
     }
-    // Find index of the last used session
-    if let Some(index) = sessions.iter().position(|session| session.name == default_session) {
-        default_session_index = index;
+
+    fn save(&self, state_file: &str) -> io::Result<()> {
+        let content = toml::to_string(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        state_lock::write_atomically(Path::new(state_file), content.as_bytes())
     }
-    Ok((default_user_index, default_session_index))
-}
 
-pub fn write_selection(file_path: &String, selected_user: &User, selected_session: &Session) -> io::Result<()> {
-    // Open the file in write mode, truncate the contents if the file already exists
-    let mut file = File::create(file_path)?;
-    // Write the selected username on the first line, selected session to the second
-    writeln!(file, "{}\n{}", selected_user.name,selected_session.name)?;
-    Ok(())
+    pub fn record(&mut self, tty_name: &str, user: &User, session: &Session) {
+        self.last_session.insert(user.name.clone(), session.id.clone());
+        self.last_user.insert(tty_name.to_string(), user.name.clone());
+    }
+
+    // Index of the last user of the tty, the first user if there is none
+    pub fn user_index(&self, tty_name: &str, users: &[User]) -> usize {
+        self.last_user.get(tty_name)
+            .or(self.legacy_user.as_ref())
+            .and_then(|username| users.iter().position(|user| &user.name == username))
+            .unwrap_or(0)
+    }
+
+    // Index of the last session of the user
+    pub fn session_index(&self, username: &str, sessions: &[Session]) -> Option<usize> {
+        let last_session = self.last_session.get(username)?;
+        // The default file of older versions has the desktop file name of graphical sessions
+        sessions.iter().position(|session| session.matches(last_session))
+            .or_else(|| sessions.iter().position(|session| session.id == format!("{}/{}", session.session_type, last_session)))
+    }
 }
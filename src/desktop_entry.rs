@@ -0,0 +1,312 @@
+use std::{collections::HashMap, env, fs, io, os::unix::fs::PermissionsExt, path::{Path, PathBuf}};
+
+// Keys of the [Desktop Entry] group of a .desktop file as described by the Desktop Entry Specification
+#[derive(Debug, Clone, Default)]
+pub struct DesktopEntry {
+    path: PathBuf,
+    entries: HashMap<String, String>,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Resolve the escape sequences of string values
+fn unescape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => result.push(' '),
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some('\\') => result.push('\\'),
+            // Unknown escapes are kept as they are
+            Some(other) => {
+                result.push('\\');
+                result.push(other);
+            }
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+// Check if a program exists and is executable, programs without a slash are searched in PATH
+fn program_exists(program: &str) -> bool {
+    let is_executable = |path: &Path| {
+        fs::metadata(path).map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0).unwrap_or(false)
+    };
+    if program.contains('/') {
+        return is_executable(Path::new(program));
+    }
+    env::var_os("PATH")
+        .map(|path| env::split_paths(&path).any(|dir| is_executable(&dir.join(program))))
+        .unwrap_or(false)
+}
+
+// Locale of messages as set in the environment, None for the C locale
+pub fn message_locale() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"].iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .filter(|value| value != "C" && value != "POSIX" && !value.starts_with("C."))
+}
+
+// Keys to look up for a locale of the form lang_COUNTRY.ENCODING@MODIFIER, best match first.
+// The encoding is ignored as the specification says.
+fn locale_keys(key: &str, locale: &str) -> Vec<String> {
+    let (locale, modifier) = match locale.split_once('@') {
+        Some((locale, modifier)) => (locale, Some(modifier)),
+        None => (locale, None),
+    };
+    let locale = locale.split('.').next().unwrap_or(locale);
+    let (lang, country) = match locale.split_once('_') {
+        Some((lang, country)) => (lang, Some(country)),
+        None => (locale, None),
+    };
+
+    let mut keys = Vec::new();
+    if let (Some(country), Some(modifier)) = (country, modifier) {
+        keys.push(format!("{}[{}_{}@{}]", key, lang, country, modifier));
+    }
+    if let Some(country) = country {
+        keys.push(format!("{}[{}_{}]", key, lang, country));
+    }
+    if let Some(modifier) = modifier {
+        keys.push(format!("{}[{}@{}]", key, lang, modifier));
+    }
+    keys.push(format!("{}[{}]", key, lang));
+    keys
+}
+
+impl DesktopEntry {
+    pub fn from_file(path: &Path) -> io::Result<DesktopEntry> {
+        let content = fs::read_to_string(path)?;
+        let mut entry = DesktopEntry::parse(&content)?;
+        entry.path = path.to_path_buf();
+        Ok(entry)
+    }
+
+    // Only the [Desktop Entry] group is read, other groups (e.g. actions) are skipped
+    pub fn parse(content: &str) -> io::Result<DesktopEntry> {
+        let mut entries = HashMap::new();
+        let mut in_main_group = false;
+        let mut main_group_found = false;
+
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') {
+                in_main_group = line == "[Desktop Entry]";
+                main_group_found |= in_main_group;
+                continue;
+            }
+            if !in_main_group {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(invalid(format!("line {} is neither a group, a key nor a comment", number + 1)));
+            };
+            // The first occurrence of a key wins
+            entries.entry(key.trim().to_string()).or_insert_with(|| value.trim().to_string());
+        }
+
+        if !main_group_found {
+            return Err(invalid("no [Desktop Entry] group".to_string()));
+        }
+        Ok(DesktopEntry { path: PathBuf::new(), entries })
+    }
+
+    pub fn string(&self, key: &str) -> Option<String> {
+        self.entries.get(key).map(|value| unescape(value))
+    }
+
+    pub fn boolean(&self, key: &str) -> bool {
+        self.entries.get(key).is_some_and(|value| value == "true")
+    }
+
+    // Values separated by ';', "\;" is a literal semicolon
+    pub fn list(&self, key: &str) -> Vec<String> {
+        let Some(value) = self.entries.get(key) else {
+            return Vec::new();
+        };
+        // Split before unescaping, so an escaped backslash in front of a separator stays a backslash
+        let mut items = Vec::new();
+        let mut item = String::new();
+        let mut chars = value.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some(';') => item.push(';'),
+                    Some(other) => {
+                        item.push('\\');
+                        item.push(other);
+                    }
+                    None => item.push('\\'),
+                },
+                ';' => items.push(unescape(&std::mem::take(&mut item))),
+                _ => item.push(c),
+            }
+        }
+        if !item.is_empty() {
+            items.push(unescape(&item));
+        }
+        items
+    }
+
+    // Value of a localestring key for the given locale, falls back to the key without locale
+    pub fn localized_string(&self, key: &str, locale: Option<&str>) -> Option<String> {
+        locale
+            .and_then(|locale| locale_keys(key, locale).iter().find_map(|localized_key| self.string(localized_key)))
+            .or_else(|| self.string(key))
+    }
+
+    // Name in the locale of messages
+    pub fn name(&self) -> Option<String> {
+        self.localized_string("Name", message_locale().as_deref())
+    }
+
+    // Comment in the locale of messages
+    pub fn comment(&self) -> Option<String> {
+        self.localized_string("Comment", message_locale().as_deref())
+    }
+
+    pub fn desktop_names(&self) -> Vec<String> {
+        self.list("DesktopNames")
+    }
+
+    // Hidden entries count as deleted, NoDisplay ones must not be shown
+    pub fn is_hidden(&self) -> bool {
+        self.boolean("Hidden") || self.boolean("NoDisplay")
+    }
+
+    // False if TryExec names a program that isn't installed
+    pub fn try_exec_found(&self) -> bool {
+        self.string("TryExec").is_none_or(|program| program_exists(&program))
+    }
+
+    // Split the Exec key into the program and its arguments.
+    // Field codes for files and URLs expand to nothing, since sessions are started without any.
+    pub fn exec_argv(&self) -> io::Result<Vec<String>> {
+        let exec = self.string("Exec").ok_or_else(|| invalid("no Exec key".to_string()))?;
+
+        let mut argv = Vec::new();
+        let mut arg = String::new();
+        // Set once the current argument has content or quotes, so "" is kept as an empty argument
+        let mut in_arg = false;
+        let mut in_quotes = false;
+        let mut chars = exec.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => {
+                    in_quotes = !in_quotes;
+                    in_arg = true;
+                }
+                // Inside quotes a backslash escapes ", `, $ and \
+                '\\' if in_quotes => match chars.next() {
+                    Some(escaped @ ('"' | '`' | '$' | '\\')) => arg.push(escaped),
+                    Some(other) => {
+                        arg.push('\\');
+                        arg.push(other);
+                    }
+                    None => return Err(invalid("unterminated escape in Exec".to_string())),
+                },
+                ' ' | '\t' if !in_quotes => {
+                    if in_arg {
+                        argv.push(std::mem::take(&mut arg));
+                        in_arg = false;
+                    }
+                }
+                '%' if !in_quotes => {
+                    match chars.next() {
+                        Some('%') => arg.push('%'),
+                        Some('c') => arg.push_str(&self.name().unwrap_or_default()),
+                        Some('k') => arg.push_str(&self.path.to_string_lossy()),
+                        // %f %F %u %U %i and the deprecated codes expand to nothing
+                        Some(_) => {}
+                        None => return Err(invalid("incomplete field code in Exec".to_string())),
+                    }
+                    // A field code alone doesn't make an argument if it expanded to nothing
+                    in_arg |= !arg.is_empty();
+                }
+                _ => {
+                    arg.push(c);
+                    in_arg = true;
+                }
+            }
+        }
+        if in_quotes {
+            return Err(invalid("unterminated quote in Exec".to_string()));
+        }
+        if in_arg {
+            argv.push(arg);
+        }
+        if argv.is_empty() {
+            return Err(invalid("empty Exec key".to_string()));
+        }
+        Ok(argv)
+    }
+}
+
+// Print how the desktop files of the given directories are parsed
+pub fn main() {
+    let mut dirs: Vec<String> = env::args().skip(1).collect();
+    if dirs.is_empty() {
+        dirs = vec!["/usr/share/xsessions".to_string(), "/usr/share/wayland-sessions".to_string()];
+    }
+
+    // Quoting, escapes, field codes and lists as in the specification
+    let check = DesktopEntry::parse(concat!(
+        "[Desktop Entry]\n",
+        "Name=Sway\n",
+        "Exec=env WLR_NO_HARDWARE_CURSORS=1 \"sway\" --config \"/etc/sway/my \\\\\"config\\\\\"\" \"\" %F\n",
+        "DesktopNames=sway;wlroots\\;test;\n",
+        "[Desktop Action new]\n",
+        "Exec=other\n",
+    )).expect("valid desktop entry");
+    assert_eq!(check.exec_argv().expect("valid Exec"), ["env", "WLR_NO_HARDWARE_CURSORS=1", "sway", "--config", "/etc/sway/my \"config\"", ""]);
+    assert_eq!(check.desktop_names(), ["sway", "wlroots;test"]);
+    assert!(DesktopEntry::parse("[Desktop Entry]\nExec=\"sway\n").expect("valid desktop entry").exec_argv().is_err());
+
+    // Locale matching order: lang_COUNTRY@MODIFIER, lang_COUNTRY, lang@MODIFIER, lang, no locale
+    let localized = DesktopEntry::parse(concat!(
+        "[Desktop Entry]\n",
+        "Name=Plasma (Wayland)\n",
+        "Name[de]=Plasma (Wayland) de\n",
+        "Name[de_AT]=Plasma (Wayland) de_AT\n",
+        "Name[sr@latin]=Plasma (Wayland) sr@latin\n",
+        "Name[ja]=プラズマ (Wayland)\n",
+    )).expect("valid desktop entry");
+    let name = |locale| localized.localized_string("Name", locale).expect("name");
+    assert_eq!(name(Some("de_AT.UTF-8")), "Plasma (Wayland) de_AT");
+    assert_eq!(name(Some("de_DE.UTF-8@euro")), "Plasma (Wayland) de");
+    assert_eq!(name(Some("sr_RS@latin")), "Plasma (Wayland) sr@latin");
+    assert_eq!(name(Some("ja_JP.UTF-8")), "プラズマ (Wayland)");
+    assert_eq!(name(Some("fr_FR.UTF-8")), "Plasma (Wayland)");
+    assert_eq!(name(None), "Plasma (Wayland)");
+    println!("Desktop entry checks passed");
+
+    for dir in dirs {
+        let Ok(files) = fs::read_dir(&dir) else {
+            println!("{}: not readable", dir);
+            continue;
+        };
+        for file in files.flatten() {
+            let path = file.path();
+            match DesktopEntry::from_file(&path) {
+                Ok(entry) => println!(
+                    "{}: name {:?}, comment {:?}, exec {:?}, hidden {}, try exec found {}, desktop names {:?}",
+                    path.display(), entry.name(), entry.comment(), entry.exec_argv(), entry.is_hidden(), entry.try_exec_found(), entry.desktop_names()),
+                Err(e) => println!("{}: {}", path.display(), e),
+            }
+        }
+    }
+}
@@ -1,11 +1,15 @@
-use std::{env, fs, ptr, process::{Command, Stdio}, mem::zeroed, ffi::CString, os::unix::{fs::chown, process::CommandExt}, path::{Path, PathBuf}};
+use std::{env, fs, io, io::Write, ffi::CString, process::{Command, Stdio}, os::unix::{fs::{chown, FileExt, OpenOptionsExt}, process::CommandExt}, path::{Path, PathBuf}};
 use regex::Regex;
-use libc::{self, gettimeofday, timeval, setutxent, utmpx, c_short, pututxline, endutxent, getutxline, c_char, sleep};
+use libc::{self, sleep};
 
-use crate::session::Session;
+use crate::session::{self, LaunchMode, Session};
+use crate::settings::{Accounting, SessionRule};
+use crate::syslog;
+use crate::utmp::{self, RecordType, UtmpRecord};
 use crate::user::User;
 
-fn prepare_environment(user: &User, session: &Session) {
+fn prepare_environment(user: &User, session: &Session, pam_env: &[(String, String)]) {
+    let from_pam = |name: &str| pam_env.iter().any(|(pam_name, _)| pam_name == name);
 
     // Set user-specific environment variables
     env::set_var("SHELL", &user.shell);
@@ -13,19 +17,39 @@ fn prepare_environment(user: &User, session: &Session) {
     env::set_var("USER", &user.name);
     env::set_var("PWD", &user.homedir);
     env::set_var("HOME", &user.homedir);
+    // Default PATH of login(1), so the PATH of nclogin doesn't leak into the session
+    env::set_var("PATH", if user.uid == 0 {
+        "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin"
+    } else {
+        "/usr/local/bin:/usr/bin:/bin"
+    });
 
     // Set session-specific environment variables
     env::set_var("XDG_SESSION_TYPE", session.session_type.to_string());
-    env::set_var("XDG_CURRENT_DESKTOP", &session.name);
+    if session.desktop_names.is_empty() {
+        env::set_var("XDG_CURRENT_DESKTOP", &session.name);
+    } else {
+        env::set_var("XDG_CURRENT_DESKTOP", session.desktop_names.join(":"));
+    }
     env::set_var("XDG_DATA_HOME", format!("{}{}", &user.homedir,"/.local/share"));
     env::set_var("XDG_CONFIG_HOME", format!("{}{}", &user.homedir,"/.config"));
     env::set_var("XDG_CACHE_HOME", format!("{}{}", &user.homedir,"/.cache"));
     env::set_var("XDG_SESSION_CLASS", "user");
     env::set_var("XDG_RUNTIME_DIR", format!("/run/user/{}", user.uid));
     env::set_var("XDG_VTNR", get_tty_nr().unwrap_or(0).to_string());
-    env::set_var("XDG_SEAT", get_seat_name());
-    env::set_var("XDG_SESSION_ID", get_session_id(&get_tty_name(), &user.name).unwrap_or(0).to_string());
 
+    // Only ask loginctl if pam_systemd didn't tell us
+    if !from_pam("XDG_SEAT") {
+        env::set_var("XDG_SEAT", get_seat_name());
+    }
+    if !from_pam("XDG_SESSION_ID") {
+        env::set_var("XDG_SESSION_ID", get_session_id(&get_tty_name(), &user.name).unwrap_or(0).to_string());
+    }
+
+    // Variables exported by PAM modules take priority over the guesses above
+    for (name, value) in pam_env {
+        env::set_var(name, value);
+    }
 }
 
 // Function to get the tty path (/dev/tty?)
@@ -96,77 +120,90 @@ pub fn get_session_id(tty_name: &str, user_name: &str) -> Option<i32> {
     None
 }
 
-const LOGIN_PROCESS: c_short = 6;
-const USER_PROCESS: c_short = 7;
+fn add_utmpx_entry(username: &str, tty: &str, pid: i32, accounting: &Accounting) -> UtmpRecord {
+    let record = UtmpRecord::new(RecordType::UserProcess, tty).user(username).pid(pid);
+    if let Err(e) = utmp::write_record(&accounting.utmp_file, &record) {
+        syslog::error(&format!("Failed to write to utmpx: {}", e));
+    }
+    // Login record for last
+    if let Err(e) = utmp::append_record(&accounting.wtmp_file, &record) {
+        syslog::error(&format!("Failed to write to wtmp: {}", e));
+    }
+    record
+}
 
-fn add_utmpx_entry(username: &str, tty: &str, pid: i32) {
-    unsafe {
-        // Open utmpx file for updating
-        setutxent();
-
-        // Create a new utmpx entry
-        let mut entry: utmpx = zeroed();
-        entry.ut_type = USER_PROCESS;
-        entry.ut_pid = pid;
-
-        // Set the tty name (without `/dev/`)
-        let ttyname = tty.trim_start_matches("/dev/");
-        let tty_cstr = CString::new(ttyname).unwrap();
-
-        // Ensure that the ttyname fits into the field
-        let tty_len = std::cmp::min(tty_cstr.to_bytes().len(), entry.ut_line.len());
-        ptr::copy_nonoverlapping(tty_cstr.as_ptr(), entry.ut_line.as_mut_ptr(), tty_len);
-        ptr::copy_nonoverlapping(tty_cstr.as_ptr(), entry.ut_id.as_mut_ptr(), tty_len);
-
-        // Set the username (ensure it fits in the buffer)
-        let user_cstr = CString::new(username).unwrap();
-        let user_len = std::cmp::min(user_cstr.to_bytes().len(), entry.ut_user.len());
-        ptr::copy_nonoverlapping(user_cstr.as_ptr(), entry.ut_user.as_mut_ptr(), user_len);
-
-        // Set timestamp
-        let mut tv: timeval = zeroed();
-        gettimeofday(&mut tv, ptr::null_mut());
-        entry.ut_tv.tv_sec = tv.tv_sec as i32;
-        entry.ut_tv.tv_usec = tv.tv_usec as i32;
-
-        // Write entry to utmpx
-        let result = pututxline(&mut entry);
-        if result.is_null() {
-            eprintln!("Failed to write to utmpx.");
-        }
-        // Close utmpx
-        endutxent();
+// Record a failed login for lastb
+pub fn add_btmp_entry(username: &str, tty: &str, accounting: &Accounting) {
+    let record = UtmpRecord::new(RecordType::LoginProcess, tty).user(username).pid(std::process::id() as i32);
+    if let Err(e) = utmp::append_record(&accounting.btmp_file, &record) {
+        syslog::error(&format!("Failed to write to btmp: {}", e));
     }
 }
 
+// ll_time is 32 bit on x86_64 for compatibility with i386 and on 32 bit targets, time_t everywhere else
+#[cfg(any(target_arch = "x86_64", target_pointer_width = "32"))]
+type LastlogTime = i32;
+#[cfg(not(any(target_arch = "x86_64", target_pointer_width = "32")))]
+type LastlogTime = i64;
+
+// Layout of /var/log/lastlog, one record per uid at offset uid * size_of::<Lastlog>()
+#[repr(C)]
+struct Lastlog {
+    ll_time: LastlogTime,
+    ll_line: [u8; 32],
+    ll_host: [u8; 256],
+}
 
-fn remove_utmpx_entry() {
-    unsafe {
-        setutxent(); // Open utmpx file for reading & writing
-        let tty_name = get_tty_name();
-        let mut entry: utmpx = zeroed();
-        let c_tty = CString::new(tty_name.clone()).unwrap();
-        ptr::copy_nonoverlapping(c_tty.as_ptr(), entry.ut_line.as_mut_ptr(), tty_name.len());
-
-        // Find the entry
-        while let Some(current) = getutxline(&mut entry).as_mut() {
-            if current.ut_line == entry.ut_line {
-                current.ut_type = LOGIN_PROCESS;
-                // Set ut_user to "LOGIN" (32 bytes, padded with 0)
-                let user_bytes = b"LOGIN";
-                let mut user_buf = [0 as c_char; 32]; // Use c_char for this array
-                for (i, &byte) in user_bytes.iter().enumerate() {
-                    user_buf[i] = byte as c_char;
-                }
-                current.ut_user = user_buf;
+// Remember the login time and tty shown by lastlog and at the next login
+fn update_lastlog(uid: u32, tty: &str, accounting: &Accounting) -> io::Result<()> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as LastlogTime)
+        .unwrap_or(0);
+    let mut record = Lastlog { ll_time: now, ll_line: [0; 32], ll_host: [0; 256] };
+    let line = tty.trim_start_matches("/dev/").as_bytes();
+    let line_len = line.len().min(record.ll_line.len());
+    record.ll_line[..line_len].copy_from_slice(&line[..line_len]);
+
+    let record_size = std::mem::size_of::<Lastlog>();
+    let bytes = unsafe { std::slice::from_raw_parts(&record as *const Lastlog as *const u8, record_size) };
+    let file = fs::OpenOptions::new().write(true).create(true).truncate(false).open(&accounting.lastlog_file)?;
+    file.write_all_at(bytes, uid as u64 * record_size as u64)
+}
 
-                pututxline(current); // Write update
-                break;
-            }
-        }
+// Mark the session as dead in utmp and write the logout record for last
+fn remove_utmpx_entry(record: &UtmpRecord, accounting: &Accounting) {
+    let logout = record.logout();
+    if let Err(e) = utmp::write_record(&accounting.utmp_file, &logout) {
+        syslog::error(&format!("Failed to write to utmpx: {}", e));
+    }
+    if let Err(e) = utmp::append_record(&accounting.wtmp_file, &logout) {
+        syslog::error(&format!("Failed to write to wtmp: {}", e));
+    }
+}
 
-        endutxent(); // Close utmpx
+// Switch the process to an unprivileged user for good, used for the greeter.
+// Does nothing if nclogin doesn't run as root (e.g. in demo mode).
+pub fn drop_privileges(user_name: &str) -> io::Result<()> {
+    if unsafe { libc::geteuid() } != 0 {
+        return Ok(());
     }
+    let user = nix::unistd::User::from_name(user_name)?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Greeter user {} not found", user_name)))?;
+    let gid = user.gid.as_raw();
+    let uid = user.uid.as_raw();
+
+    // Groups first, after setuid the process isn't allowed to change them anymore
+    unsafe {
+        if libc::setgroups(1, &gid) != 0 || libc::setgid(gid) != 0 || libc::setuid(uid) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Make sure root privileges can't be regained
+        if uid != 0 && libc::setuid(0) == 0 {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "Failed to drop root privileges"));
+        }
+    }
+    Ok(())
 }
 
 fn change_tty_ownership(user_uid: u32, tty_path_str: &str) -> Result<(), nix::Error> {
@@ -174,7 +211,72 @@ fn change_tty_ownership(user_uid: u32, tty_path_str: &str) -> Result<(), nix::Er
     Ok(())
 }
 
-pub fn exec_session_as_user(user: &User, session: &Session) {
+// Embed the default wrapper, it sources the profile files and execs the session
+const DEFAULT_SESSION_WRAPPER: &str = include_str!("../config/session-wrapper");
+
+// Install the default session wrapper if the file doesn't exist
+pub fn generate_default_session_wrapper(file_path: &str) -> io::Result<()> {
+    let path = Path::new(file_path);
+    if path.exists() {
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = fs::OpenOptions::new().write(true).create_new(true).mode(0o755).open(path)?;
+    file.write_all(DEFAULT_SESSION_WRAPPER.as_bytes())
+}
+
+// Groups of the user from NSS (/etc/group, LDAP, ...), including the primary group
+fn get_user_groups(user: &User) -> Vec<libc::gid_t> {
+    let primary_group = nix::unistd::Gid::from_raw(user.gid as libc::gid_t);
+    let groups = CString::new(user.name.as_str())
+        .map_err(|_| nix::Error::EINVAL)
+        .and_then(|name| nix::unistd::getgrouplist(&name, primary_group));
+    match groups {
+        Ok(groups) => groups.into_iter().map(|gid| gid.as_raw()).collect(),
+        Err(e) => {
+            syslog::error(&format!("Failed to get the groups of {}: {}", user.name, e));
+            vec![primary_group.as_raw()]
+        }
+    }
+}
+
+// Names of the groups of the user, for the session rules
+pub fn get_user_group_names(user: &User) -> Vec<String> {
+    get_user_groups(user)
+        .into_iter()
+        .filter_map(|gid| nix::unistd::Group::from_gid(nix::unistd::Gid::from_raw(gid)).ok().flatten())
+        .map(|group| group.name)
+        .collect()
+}
+
+// Switch the command to the user with all of its groups when it is spawned
+// Command::uid would drop the supplementary groups, so the groups are set before switching the user
+pub fn run_as_user(cmd: &mut Command, user: &User) {
+    // Resolved before forking, NSS lookups are not allowed between fork and exec
+    let groups = get_user_groups(user);
+    let uid = user.uid as libc::uid_t;
+    let gid = user.gid as libc::gid_t;
+    unsafe {
+        cmd.pre_exec(move || {
+            if libc::setgroups(groups.len(), groups.as_ptr()) != 0
+                || libc::setgid(gid) != 0
+                || libc::setuid(uid) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+pub fn exec_session_as_user(user: &User, session: &Session, wrapper: Option<&str>, pam_env: &[(String, String)], accounting: &Accounting, rules: &[SessionRule]) {
+    // The greeter only offers allowed sessions, check again with the current groups before anything is started
+    if !session::session_allowed(rules, &user.name, &get_user_group_names(user), session) {
+        syslog::error(&format!("{} is not allowed to start {}", user.name, session.id));
+        return;
+    }
+
     // Get tty infos
     let tty_path = get_tty_path();
     let tty_name = get_tty_name();
@@ -184,16 +286,48 @@ pub fn exec_session_as_user(user: &User, session: &Session) {
 
     // Cd to user's home directory
     if let Err(e) = std::env::set_current_dir(&user.homedir) {
-        eprintln!("Failed to change directory to home directory: {}", e);
+        syslog::error(&format!("Failed to change directory to home directory: {}", e));
     }
 
-    prepare_environment(user, session);
+    prepare_environment(user, session, pam_env);
 
     // Execute the session / shell
-    let mut cmd = Command::new(&session.cmd);
+    let mut cmd = match wrapper {
+        // The wrapper sets up the environment and execs the session command it gets as arguments
+        Some(wrapper) => {
+            let mut cmd = Command::new(wrapper);
+            cmd.arg(&session.cmd);
+            cmd
+        }
+        None => Command::new(&session.cmd),
+    };
+    match session.launch_mode() {
+        // The name of a wrapped program can't be changed, the wrapper is started instead
+        LaunchMode::Login if wrapper.is_some() => {}
+        LaunchMode::Login => {
+            let program = Path::new(&session.cmd)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| session.cmd.clone());
+            cmd.arg0(format!("-{}", program));
+        }
+        LaunchMode::Interactive => {
+            cmd.arg("-i");
+        }
+        LaunchMode::Exec => {}
+    }
+    cmd.args(&session.args);
+    cmd.envs(&session.env);
+    // Relative directories are relative to the home directory, which is the current directory now
+    if let Some(working_dir) = &session.working_dir {
+        if Path::new(working_dir).is_dir() {
+            cmd.current_dir(working_dir);
+        } else {
+            syslog::warning(&format!("Working directory {} not found, starting in the home directory", working_dir));
+        }
+    }
+    run_as_user(&mut cmd, user);
     cmd
-        .uid(user.uid as u32)
-        .gid(user.gid as u32)
         .stdin(Stdio::inherit())
         .stdout(Stdio::inherit())
         .stderr(Stdio::inherit());
@@ -201,12 +335,17 @@ pub fn exec_session_as_user(user: &User, session: &Session) {
         Ok(mut child) => {
             let child_pid = child.id(); // Get the child process PID
             // Add utmp entry
-            add_utmpx_entry(&user.name, &tty_name, child_pid as i32);
+            let utmp_record = add_utmpx_entry(&user.name, &tty_name, child_pid as i32, accounting);
+            if let Err(e) = update_lastlog(user.uid as u32, &tty_name, accounting) {
+                syslog::error(&format!("Failed to update lastlog: {}", e));
+            }
             let _ = child.wait(); // Wait for the child process to finish
+            // Clean up the utmp entry
+            remove_utmpx_entry(&utmp_record, accounting);
         }
         Err(e) =>
             {
-                eprintln!("Failed to execute command: {}", e);
+                syslog::error(&format!("Failed to execute command: {}", e));
                 // sleep so error msg parsing is possible
                 unsafe {
                     sleep(1);
@@ -215,7 +354,4 @@ pub fn exec_session_as_user(user: &User, session: &Session) {
     }
     // Reset tty permission
     change_tty_ownership(0, &tty_path).expect("Couldn't change tty permissions");
-
-    // Clean up the utmp entry
-    remove_utmpx_entry();
 }
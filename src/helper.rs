@@ -0,0 +1,258 @@
+use std::{io, os::unix::net::UnixStream, process::Command};
+
+use crate::auth_user::{auth_user, autologin_user, AuthError, AuthSession, Authenticator, Conversation, MessageStyle};
+use crate::ipc::{self, PowerAction, Reply, Request, PROTOCOL_VERSION};
+use crate::secret::Secret;
+use crate::session::Session;
+use crate::settings::Settings;
+use crate::syslog;
+use crate::throttle::Throttle;
+use crate::user::User;
+use crate::default_selection::DefaultSelection;
+use crate::{environment, session, utmp};
+
+// Forwards the PAM conversation to the greeter
+struct GreeterProxy {
+    stream: UnixStream,
+}
+
+impl GreeterProxy {
+    fn new(stream: &UnixStream) -> Result<GreeterProxy, AuthError> {
+        let stream = stream.try_clone().map_err(|_| AuthError::ConversationAborted)?;
+        Ok(GreeterProxy { stream })
+    }
+}
+
+impl Conversation for GreeterProxy {
+    fn converse(&mut self, style: MessageStyle, message: &str) -> Option<Secret> {
+        ipc::send_reply(&mut self.stream, &Reply::Converse { style, message: message.to_string() }).ok()?;
+        match ipc::read_request(&mut self.stream) {
+            Ok(Request::Answer(answer)) => answer,
+            _ => None,
+        }
+    }
+}
+
+// Successful login waiting for the greeter to exit
+struct PendingLogin {
+    user: usize,
+    session: usize,
+    auth_session: Box<dyn AuthSession>,
+}
+
+// Privileged side of nclogin: owns PAM, the login throttle, utmp and starting sessions.
+// The greeter is not trusted, it can only choose from the users and sessions loaded before it was started.
+pub struct Helper<'a> {
+    pub config: &'a Settings,
+    pub authenticator: &'a dyn Authenticator,
+    pub users: &'a [User],
+    pub sessions: &'a [Session],
+    pub tty_path: String,
+    // Demo mode neither starts sessions nor powers off
+    pub demo: bool,
+}
+
+fn protocol_error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("protocol error: {}", message))
+}
+
+fn wait_for_greeter(greeter_pid: libc::pid_t) {
+    let mut status = 0;
+    unsafe {
+        libc::waitpid(greeter_pid, &mut status, 0);
+    }
+}
+
+// Keep a successful login until the greeter asks to start it
+fn keep_login(pending: &mut Option<PendingLogin>, result: Result<PendingLogin, AuthError>) -> Reply {
+    match result {
+        Ok(login) => {
+            *pending = Some(login);
+            Reply::Login(Ok(()))
+        }
+        Err(e) => Reply::Login(Err(e)),
+    }
+}
+
+impl Helper<'_> {
+    // Serve the greeter until it asks to start a session or exits
+    pub fn run(&self, mut stream: UnixStream, greeter_pid: libc::pid_t) -> io::Result<()> {
+        let result = self.serve(&mut stream);
+        // Reap the greeter, the session can only use the tty after the greeter is gone
+        drop(stream);
+        wait_for_greeter(greeter_pid);
+        let pending = result?;
+
+        if let Some(mut pending) = pending {
+            let user = &self.users[pending.user];
+            let session = &self.sessions[pending.session];
+            if self.demo {
+                pending.auth_session.close_session();
+                return Ok(());
+            }
+            // Remember the user of this tty and the session of the user if activated
+            // The file is read again under a lock, so selections of other ttys made in the meantime are kept
+            if self.config.login_behaviour.write_last_to_default_selection {
+                let behaviour = &self.config.login_behaviour;
+                let tty_name = environment::get_tty_name();
+                let result = DefaultSelection::update(&behaviour.state_file, &behaviour.default_selection_file,
+                    |selection| selection.record(&tty_name, user, session));
+                if let Err(e) = result {
+                    syslog::error(&format!("Error writing state file: {}", e));
+                }
+            }
+            let pam_env = pending.auth_session.env_list();
+            let wrapper = match session.wrapper.as_deref() {
+                Some("") => None,
+                Some(wrapper) => Some(wrapper),
+                None => self.config.login_behaviour.session_wrapper_for(&session.session_type.to_string()),
+            };
+            environment::exec_session_as_user(user, session, wrapper, &pam_env, &self.config.accounting, &self.config.session_rules);
+            // Close the PAM session after the session process exited
+            pending.auth_session.close_session();
+        }
+        Ok(())
+    }
+
+    // Returns the login to start, None if the greeter exited without one
+    fn serve(&self, stream: &mut UnixStream) -> io::Result<Option<PendingLogin>> {
+        match ipc::read_request(stream)? {
+            Request::Hello { version } => {
+                ipc::send_reply(stream, &Reply::Hello { version: PROTOCOL_VERSION })?;
+                if version != PROTOCOL_VERSION {
+                    return Err(protocol_error(&format!("greeter speaks version {}, expected {}", version, PROTOCOL_VERSION)));
+                }
+            }
+            _ => return Err(protocol_error("expected hello")),
+        }
+
+        let throttle = Throttle::new(&self.config.login_throttle);
+        let mut pending: Option<PendingLogin> = None;
+        loop {
+            let request = match ipc::read_request(stream) {
+                Ok(request) => request,
+                // The greeter exited, e.g. because it crashed
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            match request {
+                Request::LockStatus { username } => {
+                    let remaining = throttle.remaining(&username, &self.tty_path);
+                    ipc::send_reply(stream, &Reply::LockStatus { remaining })?;
+                }
+                Request::Login { username, session } => {
+                    // A new attempt replaces a previous login that wasn't started
+                    pending = None;
+                    let session = session as usize;
+                    if session >= self.sessions.len() {
+                        return Err(protocol_error("unknown session"));
+                    }
+                    let result = self.login(stream, &throttle, &username, session);
+                    ipc::send_reply(stream, &keep_login(&mut pending, result))?;
+                }
+                Request::Autologin => {
+                    pending = None;
+                    let result = self.autologin(stream);
+                    ipc::send_reply(stream, &keep_login(&mut pending, result))?;
+                }
+                Request::StartSession => {
+                    if pending.is_none() {
+                        return Err(protocol_error("no successful login to start"));
+                    }
+                    return Ok(pending);
+                }
+                Request::Power(action) => {
+                    ipc::send_reply(stream, &Reply::Power(self.power(action)))?;
+                }
+                Request::Hello { .. } | Request::Answer(_) => return Err(protocol_error("unexpected request")),
+            }
+        }
+    }
+
+    fn login(&self, stream: &UnixStream, throttle: &Throttle, username: &str, session: usize) -> Result<PendingLogin, AuthError> {
+        let Some(user) = self.users.iter().position(|user| user.name == username) else {
+            return Err(AuthError::UserUnknown);
+        };
+        // Don't ask for a password for a session the user can't start
+        let groups = environment::get_user_group_names(&self.users[user]);
+        if !session::session_allowed(&self.config.session_rules, username, &groups, &self.sessions[session]) {
+            return Err(AuthError::SessionNotAllowed);
+        }
+        // The throttle is enforced here, the greeter only shows the countdown
+        if let Some(remaining) = throttle.remaining(username, &self.tty_path) {
+            return Err(AuthError::Throttled(remaining));
+        }
+
+        let conversation = GreeterProxy::new(stream)?;
+        let pam_service = self.config.login_behaviour.pam_service_for(&self.sessions[session].session_type.to_string());
+        match auth_user(self.authenticator, pam_service, username, &self.tty_path, Box::new(conversation)) {
+            Ok(auth_session) => {
+                throttle.record_success(username);
+                Ok(PendingLogin { user, session, auth_session })
+            }
+            Err(e) => {
+                // Only wrong credentials are failed logins, not e.g. an aborted conversation or a broken PAM stack
+                if e.is_credential_failure() {
+                    throttle.record_failure(username, &self.tty_path);
+                    if !self.demo {
+                        environment::add_btmp_entry(username, &self.tty_path, &self.config.accounting);
+                    }
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn autologin(&self, stream: &UnixStream) -> Result<PendingLogin, AuthError> {
+        // Only the configuration of the helper decides who is logged in without a password
+        let Some(target) = self.config.autologin.for_tty(&environment::get_tty_name()) else {
+            return Err(AuthError::PermissionDenied);
+        };
+        let Some(user) = self.users.iter().position(|user| user.name == target.user) else {
+            return Err(AuthError::UserUnknown);
+        };
+        let Some(session) = self.sessions.iter().position(|session| session.matches(&target.session)) else {
+            syslog::error(&format!("Autologin session {} not found", target.session));
+            return Err(AuthError::SessionFailed);
+        };
+        // The rules apply to autologin too, the session isn't opened if the user may not start it
+        let groups = environment::get_user_group_names(&self.users[user]);
+        if !session::session_allowed(&self.config.session_rules, &target.user, &groups, &self.sessions[session]) {
+            return Err(AuthError::SessionNotAllowed);
+        }
+
+        let conversation = GreeterProxy::new(stream)?;
+        let auth_session = autologin_user(self.authenticator, &self.config.autologin.pam_service, &target.user, &self.tty_path, Box::new(conversation))?;
+        Ok(PendingLogin { user, session, auth_session })
+    }
+
+    fn power(&self, action: PowerAction) -> Result<(), String> {
+        let (name, mut command) = match action {
+            PowerAction::Reboot => ("reboot", Command::new("reboot")),
+            PowerAction::Shutdown => {
+                let mut command = Command::new("shutdown");
+                command.arg("--poweroff");
+                ("shutdown", command)
+            }
+        };
+        if self.demo {
+            return Err(format!("{} not possible in demo mode", name));
+        }
+        // Refuse if the logged in users can't be read, they could lose their sessions
+        match utmp::logged_in_users(&self.config.accounting.utmp_file) {
+            Err(e) => {
+                syslog::error(&format!("Failed to read utmp before {}: {}", name, e));
+                return Err(format!("{} not possible, logged in users can't be read: {}", name, e));
+            }
+            Ok(logged_in) if !logged_in.is_empty() => {
+                let sessions: Vec<String> = logged_in.iter().map(|user| format!("{} on {}", user.user, user.tty)).collect();
+                return Err(format!("{} not possible, logged in: {}", name, sessions.join(", ")));
+            }
+            Ok(_) => {}
+        }
+        match command.status() {
+            Ok(status) if status.success() => Ok(()),
+            _ => Err(format!("{} failed", name)),
+        }
+    }
+}
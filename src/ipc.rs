@@ -0,0 +1,331 @@
+use std::{io::{self, Read, Write}, os::unix::net::UnixStream};
+
+use crate::auth_user::{AuthError, Conversation, MessageStyle};
+use crate::secret::Secret;
+
+// Messages between the unprivileged greeter and the privileged helper.
+// Every message starts with a one byte tag followed by its fields:
+// integers are big endian, strings are a u32 length followed by UTF-8 bytes.
+// Bump the version on every change, both processes come from the same binary but may be mixed up during updates.
+pub const PROTOCOL_VERSION: u32 = 3;
+
+// Upper limit for strings, so a misbehaving peer can't make the helper allocate arbitrary amounts of memory
+const MAX_STRING_LEN: u32 = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerAction {
+    Reboot,
+    Shutdown,
+}
+
+// Sent by the greeter
+pub enum Request {
+    Hello { version: u32 },
+    // Seconds until the user may try to log in again
+    LockStatus { username: String },
+    // Authenticate the user for the session with the given index, the helper answers with Converse messages and a Login reply
+    Login { username: String, session: u32 },
+    // Log in the autologin target the helper has configured for the tty, answered like Login
+    Autologin,
+    // Answer to a Converse message, None aborts the conversation
+    Answer(Option<Secret>),
+    // Start the session of the successful login as soon as the greeter exited
+    StartSession,
+    Power(PowerAction),
+}
+
+// Sent by the helper
+pub enum Reply {
+    Hello { version: u32 },
+    LockStatus { remaining: Option<u64> },
+    Converse { style: MessageStyle, message: String },
+    Login(Result<(), AuthError>),
+    // Err contains the reason why the action was refused, it is shown in the greeter
+    Power(Result<(), String>),
+}
+
+fn protocol_error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("protocol error: {}", message))
+}
+
+fn write_u8(stream: &mut impl Write, value: u8) -> io::Result<()> {
+    stream.write_all(&[value])
+}
+
+fn write_u32(stream: &mut impl Write, value: u32) -> io::Result<()> {
+    stream.write_all(&value.to_be_bytes())
+}
+
+fn write_u64(stream: &mut impl Write, value: u64) -> io::Result<()> {
+    stream.write_all(&value.to_be_bytes())
+}
+
+fn write_str(stream: &mut impl Write, value: &str) -> io::Result<()> {
+    if value.len() > MAX_STRING_LEN as usize {
+        return Err(protocol_error("string too long"));
+    }
+    write_u32(stream, value.len() as u32)?;
+    stream.write_all(value.as_bytes())
+}
+
+fn read_u8(stream: &mut impl Read) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    stream.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u32(stream: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    stream.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_u64(stream: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    stream.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+fn read_str(stream: &mut impl Read) -> io::Result<String> {
+    let len = read_u32(stream)?;
+    if len > MAX_STRING_LEN {
+        return Err(protocol_error("string too long"));
+    }
+    let mut buf = vec![0u8; len as usize];
+    stream.read_exact(&mut buf)?;
+    String::from_utf8(buf).map_err(|_| protocol_error("invalid UTF-8"))
+}
+
+// Secrets are written straight from and read straight into their locked buffers
+fn write_secret(stream: &mut impl Write, secret: &Secret) -> io::Result<()> {
+    write_u32(stream, secret.len() as u32)?;
+    stream.write_all(secret.as_bytes())
+}
+
+fn read_secret(stream: &mut impl Read) -> io::Result<Secret> {
+    let len = read_u32(stream)?;
+    let mut secret = Secret::new();
+    secret.read_from(stream, len as usize)?;
+    Ok(secret)
+}
+
+fn write_style(stream: &mut impl Write, style: MessageStyle) -> io::Result<()> {
+    write_u8(stream, match style {
+        MessageStyle::PromptEchoOff => 1,
+        MessageStyle::PromptEchoOn => 2,
+        MessageStyle::ErrorMsg => 3,
+        MessageStyle::TextInfo => 4,
+    })
+}
+
+fn read_style(stream: &mut impl Read) -> io::Result<MessageStyle> {
+    match read_u8(stream)? {
+        1 => Ok(MessageStyle::PromptEchoOff),
+        2 => Ok(MessageStyle::PromptEchoOn),
+        3 => Ok(MessageStyle::ErrorMsg),
+        4 => Ok(MessageStyle::TextInfo),
+        _ => Err(protocol_error("unknown message style")),
+    }
+}
+
+fn write_auth_error(stream: &mut impl Write, error: &AuthError) -> io::Result<()> {
+    match error {
+        AuthError::ServiceNotFound(service) => {
+            write_u8(stream, 1)?;
+            write_str(stream, service)
+        }
+        AuthError::AuthFailed => write_u8(stream, 2),
+        AuthError::UserUnknown => write_u8(stream, 3),
+        AuthError::MaxTries => write_u8(stream, 4),
+        AuthError::PermissionDenied => write_u8(stream, 5),
+        AuthError::AccountExpired => write_u8(stream, 6),
+        AuthError::PasswordExpired => write_u8(stream, 7),
+        AuthError::PasswordChangeFailed => write_u8(stream, 8),
+        AuthError::CredentialsFailed => write_u8(stream, 9),
+        AuthError::SessionFailed => write_u8(stream, 10),
+        AuthError::ServiceUnavailable => write_u8(stream, 11),
+        AuthError::Misconfigured => write_u8(stream, 12),
+        AuthError::ConversationAborted => write_u8(stream, 13),
+        AuthError::Throttled(remaining) => {
+            write_u8(stream, 14)?;
+            write_u64(stream, *remaining)
+        }
+        AuthError::Other(code) => {
+            write_u8(stream, 15)?;
+            write_u32(stream, *code as u32)
+        }
+        AuthError::SessionNotAllowed => write_u8(stream, 16),
+    }
+}
+
+fn read_auth_error(stream: &mut impl Read) -> io::Result<AuthError> {
+    Ok(match read_u8(stream)? {
+        1 => AuthError::ServiceNotFound(read_str(stream)?),
+        2 => AuthError::AuthFailed,
+        3 => AuthError::UserUnknown,
+        4 => AuthError::MaxTries,
+        5 => AuthError::PermissionDenied,
+        6 => AuthError::AccountExpired,
+        7 => AuthError::PasswordExpired,
+        8 => AuthError::PasswordChangeFailed,
+        9 => AuthError::CredentialsFailed,
+        10 => AuthError::SessionFailed,
+        11 => AuthError::ServiceUnavailable,
+        12 => AuthError::Misconfigured,
+        13 => AuthError::ConversationAborted,
+        14 => AuthError::Throttled(read_u64(stream)?),
+        15 => AuthError::Other(read_u32(stream)? as i32),
+        16 => AuthError::SessionNotAllowed,
+        _ => return Err(protocol_error("unknown authentication error")),
+    })
+}
+
+pub fn send_request(stream: &mut impl Write, request: &Request) -> io::Result<()> {
+    match request {
+        Request::Hello { version } => {
+            write_u8(stream, 1)?;
+            write_u32(stream, *version)
+        }
+        Request::LockStatus { username } => {
+            write_u8(stream, 2)?;
+            write_str(stream, username)
+        }
+        Request::Login { username, session } => {
+            write_u8(stream, 3)?;
+            write_str(stream, username)?;
+            write_u32(stream, *session)
+        }
+        Request::Answer(None) => write_u8(stream, 4),
+        Request::Answer(Some(secret)) => {
+            write_u8(stream, 5)?;
+            write_secret(stream, secret)
+        }
+        Request::StartSession => write_u8(stream, 6),
+        Request::Power(PowerAction::Reboot) => write_u8(stream, 7),
+        Request::Power(PowerAction::Shutdown) => write_u8(stream, 8),
+        Request::Autologin => write_u8(stream, 9),
+    }
+}
+
+pub fn read_request(stream: &mut impl Read) -> io::Result<Request> {
+    Ok(match read_u8(stream)? {
+        1 => Request::Hello { version: read_u32(stream)? },
+        2 => Request::LockStatus { username: read_str(stream)? },
+        3 => Request::Login { username: read_str(stream)?, session: read_u32(stream)? },
+        4 => Request::Answer(None),
+        5 => Request::Answer(Some(read_secret(stream)?)),
+        6 => Request::StartSession,
+        7 => Request::Power(PowerAction::Reboot),
+        8 => Request::Power(PowerAction::Shutdown),
+        9 => Request::Autologin,
+        _ => return Err(protocol_error("unknown request")),
+    })
+}
+
+pub fn send_reply(stream: &mut impl Write, reply: &Reply) -> io::Result<()> {
+    match reply {
+        Reply::Hello { version } => {
+            write_u8(stream, 1)?;
+            write_u32(stream, *version)
+        }
+        Reply::LockStatus { remaining } => {
+            write_u8(stream, 2)?;
+            write_u64(stream, remaining.unwrap_or(0))
+        }
+        Reply::Converse { style, message } => {
+            write_u8(stream, 3)?;
+            write_style(stream, *style)?;
+            write_str(stream, message)
+        }
+        Reply::Login(Ok(())) => write_u8(stream, 4),
+        Reply::Login(Err(error)) => {
+            write_u8(stream, 5)?;
+            write_auth_error(stream, error)
+        }
+        Reply::Power(Ok(())) => write_u8(stream, 6),
+        Reply::Power(Err(reason)) => {
+            write_u8(stream, 7)?;
+            write_str(stream, reason)
+        }
+    }
+}
+
+pub fn read_reply(stream: &mut impl Read) -> io::Result<Reply> {
+    Ok(match read_u8(stream)? {
+        1 => Reply::Hello { version: read_u32(stream)? },
+        2 => {
+            let remaining = read_u64(stream)?;
+            Reply::LockStatus { remaining: if remaining > 0 { Some(remaining) } else { None } }
+        }
+        3 => Reply::Converse { style: read_style(stream)?, message: read_str(stream)? },
+        4 => Reply::Login(Ok(())),
+        5 => Reply::Login(Err(read_auth_error(stream)?)),
+        6 => Reply::Power(Ok(())),
+        7 => Reply::Power(Err(read_str(stream)?)),
+        _ => return Err(protocol_error("unknown reply")),
+    })
+}
+
+// Greeter side of the connection to the helper
+pub struct HelperClient {
+    stream: UnixStream,
+}
+
+impl HelperClient {
+    // Check that the helper speaks the same protocol version
+    pub fn connect(mut stream: UnixStream) -> io::Result<HelperClient> {
+        send_request(&mut stream, &Request::Hello { version: PROTOCOL_VERSION })?;
+        match read_reply(&mut stream)? {
+            Reply::Hello { version } if version == PROTOCOL_VERSION => Ok(HelperClient { stream }),
+            Reply::Hello { version } => Err(protocol_error(&format!("helper speaks version {}, expected {}", version, PROTOCOL_VERSION))),
+            _ => Err(protocol_error("expected hello")),
+        }
+    }
+
+    pub fn lock_remaining(&mut self, username: &str) -> io::Result<Option<u64>> {
+        send_request(&mut self.stream, &Request::LockStatus { username: username.to_string() })?;
+        match read_reply(&mut self.stream)? {
+            Reply::LockStatus { remaining } => Ok(remaining),
+            _ => Err(protocol_error("expected lock status")),
+        }
+    }
+
+    // Log in through the helper, the conversation is run in the greeter
+    pub fn login(&mut self, username: &str, session: usize, conv: &mut dyn Conversation) -> io::Result<Result<(), AuthError>> {
+        send_request(&mut self.stream, &Request::Login { username: username.to_string(), session: session as u32 })?;
+        self.converse(conv)
+    }
+
+    pub fn autologin(&mut self, conv: &mut dyn Conversation) -> io::Result<Result<(), AuthError>> {
+        send_request(&mut self.stream, &Request::Autologin)?;
+        self.converse(conv)
+    }
+
+    // Answer the helper's conversation until the login result arrives
+    fn converse(&mut self, conv: &mut dyn Conversation) -> io::Result<Result<(), AuthError>> {
+        loop {
+            match read_reply(&mut self.stream)? {
+                Reply::Converse { style, message } => {
+                    let answer = conv.converse(style, &message);
+                    send_request(&mut self.stream, &Request::Answer(answer))?;
+                }
+                Reply::Login(result) => return Ok(result),
+                _ => return Err(protocol_error("expected conversation or login result")),
+            }
+        }
+    }
+
+    // The helper starts the session once the greeter has exited
+    pub fn start_session(&mut self) -> io::Result<()> {
+        send_request(&mut self.stream, &Request::StartSession)
+    }
+
+    // Err contains the reason if the helper refused
+    pub fn power(&mut self, action: PowerAction) -> io::Result<Result<(), String>> {
+        send_request(&mut self.stream, &Request::Power(action))?;
+        match read_reply(&mut self.stream)? {
+            Reply::Power(result) => Ok(result),
+            _ => Err(protocol_error("expected power reply")),
+        }
+    }
+}
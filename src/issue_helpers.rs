@@ -1,11 +1,12 @@
 use std::fs::{create_dir_all, read_to_string, File};
 use libc::{uname, utsname};
-use std::{time::Duration, thread::sleep, path::Path, ffi::CStr, io::{BufRead, BufReader, Write}};
+use std::{path::Path, ffi::CStr, io::{BufRead, BufReader, Write}};
 use chrono::{Local, Datelike, Timelike};
 use sysinfo::{System, SystemExt};
 use libc::{ttyname, STDIN_FILENO};
-use gettextrs::{setlocale, LocaleCategory};
-use ncursesw::{endwin, getch, initscr, mvaddwstr, refresh, Origin, WideString};
+use ncursesw::{mvaddwstr, Origin, WideString};
+
+use crate::utmp;
 
 pub fn get_host_name() -> String {
     let mut uname_data = utsname {
@@ -122,16 +123,8 @@ pub fn get_current_date() -> String {
     )
 }
 
-pub fn get_logged_in_users() -> usize {
-    let mut users_count = 0;
-    let utmp_path = "/var/run/utmp";
-    if let Ok(file) = File::open(utmp_path) {
-        let reader = BufReader::new(file);
-        for _line in reader.lines() {
-            users_count += 1; // Each line represents a user process
-        }
-    }
-    users_count
+pub fn get_logged_in_users(utmp_file: &str) -> usize {
+    utmp::logged_in_users(utmp_file).map(|users| users.len()).unwrap_or(0)
 }
 
 pub fn get_uptime() -> String {
@@ -156,56 +149,22 @@ pub fn draw_on_screen(lines : Vec<String>, x : usize,y : usize)
 }
 
 
-fn show_issue_file() -> Result<(), Box<dyn std::error::Error>> {
-    setlocale(LocaleCategory::LcAll, "");
-    // Initialize ncurses
-    initscr()?;
-
-    let path = "/etc/nclogin/issue";
-
-    let lines = read_or_generate_issue_file(path);
-    draw_on_screen(lines,15, 5);
-    refresh()?;
-
-    // Wait for the user to press a key before exiting
-    getch()?;
-    sleep(Duration::from_millis(1000));
-    // End ncurses
-    endwin()?;
-
-    Ok(())
-}
-
-pub fn read_or_generate_issue_file(file_path: &str) -> Vec<String> {
-    let path = Path::new(file_path);
-
-    // Try to read the issue file
-    match read_to_string(path) {
-        Ok(issue_content) => {
-            return process_issue_content(issue_content);
-        }
-        Err(_) => {
-            // File doesn't exist or cannot be read, try to create it
-            if let Err(e) = generate_default_issue_file(file_path) {
-                eprintln!("Warning: Failed to create issue file: {}. Using default content.", e);
-            }
-        }
-    }
-
-    // Try reading again after attempting to create
-    match read_to_string(path) {
-        Ok(issue_content) => process_issue_content(issue_content),
-        Err(_) => {
-            eprintln!("Warning: Failed to read issue file even after trying to create it. Using default content.");
-            process_issue_content(DEFAULT_FILE_CONTENT.to_string())
+// Read and expand the issue file, the default content is used if it can't be read.
+// The file is under the admin's control but parsed by the unprivileged greeter, never by root.
+pub fn read_issue_file(file_path: &str, utmp_file: &str) -> Vec<String> {
+    match read_to_string(Path::new(file_path)) {
+        Ok(issue_content) => process_issue_content(issue_content, utmp_file),
+        Err(e) => {
+            eprintln!("Warning: Failed to read issue file: {}. Using default content.", e);
+            process_issue_content(DEFAULT_FILE_CONTENT.to_string(), utmp_file)
         }
     }
 }
 
 // Helper function to process content
-fn process_issue_content(issue_content: String) -> Vec<String> {
+fn process_issue_content(issue_content: String, utmp_file: &str) -> Vec<String> {
     let content = issue_content
-        .replace("%u", &get_logged_in_users().to_string())
+        .replace("%u", &get_logged_in_users(utmp_file).to_string())
         .replace("%U", &get_uptime())
         .replace("%s", &get_os_name())
         .replace("%n", &get_host_name())
@@ -221,7 +180,8 @@ fn process_issue_content(issue_content: String) -> Vec<String> {
 // Embed the file content as a static string
 const DEFAULT_FILE_CONTENT: &str = include_str!("../config/issue");
 
-pub(crate) fn generate_default_issue_file(file_path: &str) -> std::io::Result<()> {
+// Write the default issue file if it doesn't exist, the content is only read by the greeter
+pub fn generate_default_issue_file(file_path: &str) -> std::io::Result<()> {
     let path = Path::new(file_path);
 
     // If file exists, return early
@@ -240,48 +200,3 @@ pub(crate) fn generate_default_issue_file(file_path: &str) -> std::io::Result<()
 
     Ok(())
 }
-
-#[allow(dead_code)]
-pub fn main() -> Result<(), Box<dyn std::error::Error>> {
-    println!("Testing Rust System Information Functions:");
-
-    // Test get_host_name
-    let host_name = get_host_name();
-    println!("Host Name: {}", host_name);
-
-    // Test get_os_name
-    let os_name = get_os_name();
-    println!("OS Name: {}", os_name);
-
-    // Test get_os_version
-    let os_version = get_os_version();
-    println!("OS Version: {}", os_version);
-
-    // Test get_architecture
-    let architecture = get_architecture();
-    println!("Architecture: {}", architecture);
-
-    // Test get_tty_name
-    let tty_name = get_tty_name();
-    println!("TTY Name: {}", tty_name);
-
-    // Test get_current_time
-    let current_time = get_current_time();
-    println!("Current Time: {}", current_time);
-
-    // Test get_current_date
-    let current_date = get_current_date();
-    println!("Current Date: {}", current_date);
-
-    // Test get_logged_in_users
-    let logged_in_users = get_logged_in_users();
-    println!("Logged-in Users: {}", logged_in_users);
-
-    // Test get_uptime
-    let uptime = get_uptime();
-    println!("Uptime: {}", uptime);
-
-    show_issue_file()?;
-
-    Ok(())
-}
@@ -0,0 +1,17 @@
+pub mod auth_user;
+pub mod default_selection;
+pub mod desktop_entry;
+pub mod environment;
+pub mod helper;
+pub mod ipc;
+pub mod issue_helpers;
+pub mod mock_auth;
+pub mod session;
+pub mod settings;
+pub mod state_lock;
+pub mod user;
+pub mod num_lock;
+pub mod secret;
+pub mod syslog;
+pub mod throttle;
+pub mod utmp;
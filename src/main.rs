@@ -1,66 +1,93 @@
-pub mod auth_user;
-pub mod default_selection;
-pub mod environment;
-pub mod issue_helpers;
-pub mod session;
-pub mod settings;
-pub mod user;
-pub mod num_lock;
-
-use std::env;
-use std::process::Command;
+use std::{env, io};
+use std::os::unix::net::UnixStream;
 use ncursesw::*;
 use ncursesw::normal::{Attributes, ColorPair, Colors};
 use gettextrs::{setlocale, LocaleCategory};
 
-use crate::auth_user::auth_user;
-use crate::issue_helpers::draw_on_screen;
+use nclogin::{environment, issue_helpers, num_lock, session, settings, user};
+use nclogin::default_selection::DefaultSelection;
+use nclogin::auth_user::{Authenticator, Conversation, MessageStyle, PamAuthenticator};
+use nclogin::helper::Helper;
+use nclogin::ipc::{HelperClient, PowerAction};
+use nclogin::mock_auth::MockAuthenticator;
+use nclogin::secret::Secret;
+use nclogin::issue_helpers::draw_on_screen;
+use nclogin::session::{Session, SessionDiagnostic};
+use nclogin::user::User;
+use nclogin::settings::{AutologinTarget, Settings};
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Initialize ncurses
     setlocale(LocaleCategory::LcAll, "");
 
-    initscr()?;
-    keypad(stdscr(), true)?;
-    noecho()?;
-    start_color()?;
-
     let tty_path = environment::get_tty_path();
 
-    // Set the config path to default or to arg1 if provided
-    let default_path = String::from("/etc/nclogin/config.toml");
-    let args: Vec<String> = env::args().collect();
-    let config_path = args.get(1).unwrap_or(&default_path);
+    // Set the config path to default or to the first argument if provided
+    // --demo <fixture> authenticates against a mock fixture instead of PAM and doesn't start the session
+    // --check-sessions prints the sessions and the skipped files and exits
+    let mut config_path = String::from("/etc/nclogin/config.toml");
+    let mut demo_fixture: Option<String> = None;
+    let mut check_sessions = false;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--demo" {
+            // Without a fixture nclogin would silently start with PAM as root
+            let Some(fixture) = args.next() else {
+                eprintln!("Usage: nclogin [--demo <fixture>] [--check-sessions] [config file]");
+                std::process::exit(2);
+            };
+            demo_fixture = Some(fixture);
+        } else if arg == "--check-sessions" {
+            check_sessions = true;
+        } else {
+            config_path = arg;
+        }
+    }
 
     // Parse the settings TOML file into the config struct if the file exists
     // Otherwise try to create the file using default config
     // If fails use default
-    let config = settings::parse_settings(config_path);
+    let config = settings::parse_settings(&config_path);
+
+    if check_sessions {
+        let (sessions, diagnostics) = session::get_sessions(
+            &config.login_behaviour.session_file,
+            &config.login_behaviour.shells_file,
+            &config.login_behaviour.x11_session_dirs(),
+            &config.login_behaviour.wayland_session_dirs());
+        for session in &sessions {
+            println!("{} ({}): {} {}", session.id, session.name, session.cmd, session.args.join(" "));
+        }
+        for diagnostic in &diagnostics {
+            println!("skipped {}", diagnostic);
+        }
+        std::process::exit(if diagnostics.is_empty() { 0 } else { 1 });
+    }
 
-    // Read the issue file if the file exists
-    // Otherwise write default to the file
-    // If fails use default
-    let issue_lines = issue_helpers::read_or_generate_issue_file(&config.issue_file_settings.issue_file);
+    // Write the default issue file if it doesn't exist, it is read by the greeter after dropping privileges
+    if let Err(e) = issue_helpers::generate_default_issue_file(&config.issue_file_settings.issue_file) {
+        eprintln!("Warning: Failed to create issue file: {}", e);
+    }
 
-    // Read Sessions from sessions TOML file if the file exists
-    // Otherwise try to parse sessions from shell file, x11 dir and wayland dir and write them to toml file
-    let sessions = session::get_sessions(
+    // Parse sessions from shell file, x11 dirs and wayland dirs and apply the entries and overrides of the sessions TOML file
+    // Files that can't be used are skipped, the greeter shows why
+    let (sessions, session_diagnostics) = session::get_sessions(
         &config.login_behaviour.session_file,
         &config.login_behaviour.shells_file,
-        &config.login_behaviour.x11_session_folder,
-        &config.login_behaviour.wayland_session_folder)?;
+        &config.login_behaviour.x11_session_dirs(),
+        &config.login_behaviour.wayland_session_dirs());
+    for diagnostic in &session_diagnostics {
+        eprintln!("Skipped session file {}", diagnostic);
+    }
+    if sessions.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "no usable sessions found").into());
+    }
 
-    // Create color pairs
-    let colors_normal = Colors::new(
-        settings::to_color(&config.colors.normal_fg_color),
-        settings::to_color(&config.colors.normal_bg_color));
-    let colors_highlight = Colors::new(
-        settings::to_color(&config.colors.highlight_fg_color),
-        settings::to_color(&config.colors.highlight_bg_color));
-    let color_pair_normal = ColorPair::new(1,colors_normal)?;
-    let color_pair_highlight = ColorPair::new(2, colors_highlight)?;
-    let attrs = Attributes::default();
-    attr_set(attrs, color_pair_normal)?;
+    // Install the default session wrapper
+    if !config.login_behaviour.session_wrapper.is_empty() {
+        if let Err(e) = environment::generate_default_session_wrapper(&config.login_behaviour.session_wrapper) {
+            eprintln!("Warning: Failed to create session wrapper: {}", e);
+        }
+    }
 
     // Num lock on startup
     if config.login_behaviour.activate_num_lock {
@@ -70,33 +97,141 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 
 
-    // Parse users
-    let users = user::parse_valid_users(
-        &config.login_behaviour.user_file,
-        &config.login_behaviour.shells_file,
-        *&config.login_behaviour.min_uid,
-        *&config.login_behaviour.include_root_user
-    )?;
+    // Load the mock fixture in demo mode
+    let mock_authenticator = match &demo_fixture {
+        Some(fixture_path) => Some(MockAuthenticator::from_file(fixture_path)?),
+        None => None,
+    };
+    let authenticator: &dyn Authenticator = match &mock_authenticator {
+        Some(mock) => mock,
+        None => &PamAuthenticator,
+    };
 
-    let mut selected_user :usize;
-    let mut selected_session :usize;
+    // Parse users, in demo mode they come from the fixture
+    let users = match &mock_authenticator {
+        Some(mock) => mock.users(),
+        None => user::parse_valid_users(
+            &config.login_behaviour.user_file,
+            &config.login_behaviour.shells_file,
+            *&config.login_behaviour.min_uid,
+            *&config.login_behaviour.include_root_user
+        )?,
+    };
 
-    (selected_user, selected_session) = match default_selection::get_default_indices(
-        &config.login_behaviour.default_selection_file, &users, &sessions) {
-        Ok((user_index, session_index)) => (user_index, session_index),
-        Err(e) => {
-            eprintln!("Error reading default indices: {}", e);
-            (0, 0) // Default to 0, 0 if there's an error
+    // Last user of this tty and last session of every user
+    let default_selection = DefaultSelection::load(
+        &config.login_behaviour.state_file,
+        &config.login_behaviour.default_selection_file);
+
+    // The greeter runs as an unprivileged user, everything needing root is done by the helper in this process
+    let (helper_stream, greeter_stream) = UnixStream::pair()?;
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error().into()),
+        0 => {
+            drop(helper_stream);
+            environment::drop_privileges(&config.login_behaviour.greeter_user)?;
+            let helper_client = HelperClient::connect(greeter_stream)?;
+            run_greeter(&config, &users, (&sessions, &session_diagnostics), helper_client, &default_selection, mock_authenticator.is_some())
         }
-    };
+        greeter_pid => {
+            drop(greeter_stream);
+            let helper = Helper {
+                config: &config,
+                authenticator,
+                users: &users,
+                sessions: &sessions,
+                tty_path,
+                demo: mock_authenticator.is_some(),
+            };
+            helper.run(helper_stream, greeter_pid)?;
+            Ok(())
+        }
+    }
+}
+
+// Unprivileged UI, logins, power actions and starting the session are requested from the helper
+fn run_greeter(
+    config: &Settings,
+    users: &[User],
+    // Sessions and the files skipped while discovering them
+    (sessions, session_diagnostics): (&[Session], &[SessionDiagnostic]),
+    mut helper_client: HelperClient,
+    default_selection: &DefaultSelection,
+    demo: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Read before ncurses starts, so warnings aren't drawn over the screen
+    let issue_lines = issue_helpers::read_issue_file(&config.issue_file_settings.issue_file, &config.accounting.utmp_file);
+
+    // Initialize ncurses
+    initscr()?;
+    keypad(stdscr(), true)?;
+    noecho()?;
+    start_color()?;
+
+    // Create color pairs
+    let colors_normal = Colors::new(
+        settings::to_color(&config.colors.normal_fg_color),
+        settings::to_color(&config.colors.normal_bg_color));
+    let colors_highlight = Colors::new(
+        settings::to_color(&config.colors.highlight_fg_color),
+        settings::to_color(&config.colors.highlight_bg_color));
+    let color_pair_normal = ColorPair::new(1,colors_normal)?;
+    let color_pair_highlight = ColorPair::new(2, colors_highlight)?;
+    let attrs = Attributes::default();
+    attr_set(attrs, color_pair_normal)?;
 
     draw_on_screen(
-        issue_lines,
+        issue_lines.clone(),
         config.issue_file_settings.issue_col_gap,
         config.issue_file_settings.issue_row_gap);
     refresh()?;
 
+    // Preselect the last user of this tty with their last session
+    let mut selected_user = default_selection.user_index(&environment::get_tty_name(), users);
+    let last_session = |user: usize| default_selection.session_index(&users[user].name, sessions);
+    let mut selected_session = last_session(selected_user).unwrap_or(0);
+
+    // Sessions each user may start, the carousel only shows these. The helper checks again.
+    let session_access: Vec<Vec<bool>> = users.iter().map(|user| {
+        let groups = environment::get_user_group_names(user);
+        sessions.iter().map(|session| session::session_allowed(&config.session_rules, &user.name, &groups, session)).collect()
+    }).collect();
+
+    // Message shown in the status bar until the next key press
+    let mut status_message = String::new();
+    if !session_diagnostics.is_empty() {
+        status_message = format!("→ {} session files skipped, press F3 for details", session_diagnostics.len());
+    }
+
+    // Autologin configured for this tty, a key press during the countdown shows the greeter instead
+    if let Some(target) = config.autologin.for_tty(&environment::get_tty_name()) {
+        let status_bar_pos = Origin { y: getmaxyx(stdscr())?.lines - 1, x: 0 };
+        if !autologin_cancelled(config.autologin.countdown, target, status_bar_pos)? {
+            let mut conversation = GreeterConversation {
+                position: status_bar_pos,
+                status_bar_pos,
+                password_prompt: config.password_prompt.password_prompt.clone(),
+                password_stars: config.password_prompt.password_stars,
+                messages_shown: false,
+            };
+            match helper_client.autologin(&mut conversation)? {
+                Ok(()) => {
+                    let command = sessions.iter()
+                        .find(|session| session.matches(&target.session))
+                        .map_or(target.session.as_str(), |session| session.cmd.as_str());
+                    return hand_over_session(&mut helper_client, demo, status_bar_pos, &target.user, command);
+                }
+                Err(e) => status_message = format!("→ autologin failed: {}", e),
+            }
+        }
+    }
+
     loop {
+        // Move to a session the highlighted user may start
+        if !session_access[selected_user][selected_session] {
+            selected_session = next_allowed_session(&session_access[selected_user], selected_session, true);
+        }
+        let session_allowed = session_access[selected_user][selected_session];
 
         let size = getmaxyx(stdscr())?;
         curs_set(CursorType::Invisible)?;
@@ -114,6 +249,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         let mut position = Origin{y: config.user_prompt.user_option_row_gap as i32, x: config.user_prompt.user_option_col_gap as i32 };
         let status_bar_pos = Origin{y: size.lines-1, x: 0};
         clear_line(status_bar_pos)?;
+        if !status_message.is_empty() {
+            mvaddstr(status_bar_pos, &status_message)?;
+        }
 
         // Display user selection prompt
         mvaddstr(position, &config.user_prompt.user_option_prompt)?; // USER_OPTION_PROMPT
@@ -149,52 +287,87 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         mvaddstr(position, "←")?;
         attr_set(attrs, color_pair_highlight)?;
         position.x +=2;
-        mvaddstr(position, &sessions[selected_session].name)?;
+        if session_allowed {
+            mvaddstr(position, &sessions[selected_session].name)?;
+        } else {
+            mvaddstr(position, "no session available")?;
+        }
         attr_set(attrs, color_pair_normal)?;
-        position.x += sessions[selected_session].name.len() as i32 + 1;
+        // The cursor position accounts for wide characters in localized names
+        position.x = getcurx(stdscr())? + 1;
         mvaddstr(position, "→")?;
 
-        // Handle keyboard input
-        let ch = getch()?;
+        // Description of the selected session
+        let description_pos = Origin { y: position.y + 1, x: config.start_prompt.start_option_col_gap as i32 + 2 };
+        clear_line(description_pos)?;
+        if let Some(description) = sessions[selected_session].description.as_ref().filter(|_| session_allowed) {
+            mvaddstr(description_pos, description)?;
+        }
+
+        // Show a countdown while the selected user or this tty is locked after failed logins
+        let lock_remaining = helper_client.lock_remaining(&users[selected_user].name)?;
+        if let Some(remaining) = lock_remaining {
+            clear_line(status_bar_pos)?;
+            mvaddstr(status_bar_pos, format!("→ too many failed logins, try again in {} s", remaining))?;
+        }
+
+        // Handle keyboard input, wake up every second to update the countdown
+        shims::ncurses::timeout(if lock_remaining.is_some() { 1000 } else { -1 });
+        let ch = match getch() {
+            Ok(ch) => ch,
+            Err(_) if lock_remaining.is_some() => continue,
+            Err(e) => return Err(e.into()),
+        };
+        status_message.clear();
         match ch
         {
             CharacterResult::Character('k') | CharacterResult::Key(KeyBinding::UpArrow) => {
                 if selected_user > 0 {
                     selected_user -= 1;
+                    selected_session = last_session(selected_user).unwrap_or(selected_session);
                 }
             }
             CharacterResult::Character('j') | CharacterResult::Key(KeyBinding::DownArrow) => {
                 if selected_user < users.len() - 1 {
                     selected_user += 1;
+                    selected_session = last_session(selected_user).unwrap_or(selected_session);
                 }
             }
             CharacterResult::Character('h') | CharacterResult::Key(KeyBinding::LeftArrow) => {
-                if selected_session == 0 {
-                    selected_session = sessions.len() - 1;
-                } else {
-                    selected_session -= 1;
-                }
+                selected_session = next_allowed_session(&session_access[selected_user], selected_session, false);
             }
             CharacterResult::Character('l') | CharacterResult::Key(KeyBinding::RightArrow) => {
-                selected_session = (selected_session + 1) % sessions.len();
+                selected_session = next_allowed_session(&session_access[selected_user], selected_session, true);
             }
             CharacterResult::Key(KeyBinding::FunctionKey(1)) => {
                 mvaddstr(Origin { y: 0, x: 0 }, "reboot")?;
-                if issue_helpers::get_logged_in_users() < 1 {
-                    Command::new("reboot").status().unwrap();
-                } else {
-                    mvaddstr(status_bar_pos, "→ reboot not possible, users are logged in")?;
+                if let Err(reason) = helper_client.power(PowerAction::Reboot)? {
+                    status_message = format!("→ {}", reason);
                 }
             }
             CharacterResult::Key(KeyBinding::FunctionKey(2)) => {
                 mvaddstr(Origin { y: 0, x: 0 }, "shutdown")?;
-                if issue_helpers::get_logged_in_users() < 1 {
-                    Command::new("shutdown").arg("--poweroff").status().unwrap();
-                } else {
-                    mvaddstr(status_bar_pos, "→ shutdown not possible, users are logged in")?;
+                if let Err(reason) = helper_client.power(PowerAction::Shutdown)? {
+                    status_message = format!("→ {}", reason);
                 }
             }
+            CharacterResult::Key(KeyBinding::FunctionKey(3)) => {
+                show_session_diagnostics(session_diagnostics)?;
+                draw_on_screen(
+                    issue_lines.clone(),
+                    config.issue_file_settings.issue_col_gap,
+                    config.issue_file_settings.issue_row_gap);
+            }
             CharacterResult::Key(KeyBinding::Enter) | CharacterResult::Character('\n') => {
+                // No login attempts during the countdown
+                if lock_remaining.is_some() {
+                    continue;
+                }
+                if !session_allowed {
+                    status_message = format!("→ {} may not start any session", users[selected_user].name);
+                    continue;
+                }
+
                 // Print command that is executed
                 let command = &sessions[selected_session].cmd;
                 let cmd_dsp_str = format!("→ {}", command);
@@ -203,34 +376,35 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 position.y += config.password_prompt.password_row_gap as i32;
                 position.x = config.password_prompt.password_col_gap as i32;
 
-                mvaddstr(position, &config.password_prompt.password_prompt)?; // PASSWORD_PROMPT
-
-                curs_set(CursorType::Visible)?;
-
-                // Use getnstr to capture the password
-                let password = getnstr(2000)?;
+                // PAM asks for the password and everything else through the conversation
+                // The helper runs PAM and forwards the conversation
+                let mut conversation = GreeterConversation {
+                    position,
+                    status_bar_pos,
+                    password_prompt: config.password_prompt.password_prompt.clone(),
+                    password_stars: config.password_prompt.password_stars,
+                    messages_shown: false,
+                };
+                let auth_result = helper_client.login(&users[selected_user].name, selected_session, &mut conversation)?;
                 curs_set(CursorType::Invisible)?;
                 position.x = config.password_prompt.password_col_gap as i32;
-                position.y = position.y + 2;
+                position.y += 2;
 
                 wmove(stdscr(), position)?;
 
-                if auth_user(&users[selected_user].name, &password, &tty_path) {
-                    // Write default selection if activated
-                    if config.login_behaviour.write_last_to_default_selection {
-                        match default_selection::write_selection(&config.login_behaviour.default_selection_file, &users[selected_user], &sessions[selected_session]) {
-                            Err(e) => eprintln!("Error writing default File: {}", e),
-                            _ => {}
+                match auth_result {
+                    Ok(()) => {
+                        // Give the user time to read messages like password expiry warnings
+                        if conversation.messages_shown {
+                            mvaddstr(position, "Press any key to continue...")?;
+                            getch()?;
                         }
+                        return hand_over_session(&mut helper_client, demo, position, &users[selected_user].name, command);
+                    }
+                    Err(e) => {
+                        clear_line(position)?;
+                        mvaddstr(position, format!("{} Press enter to try again...", e))?;
                     }
-                    clear()?;
-                    refresh()?;
-                    endwin()?;
-                    environment::exec_session_as_user(&users[selected_user], &sessions[selected_session]);
-                    return Ok(());
-                } else {
-                    mvaddstr(position, "Authentication failed. Press enter to try again...")?;
-                    position.y -= 2;
                 }
             }
             // If any other key do nothing
@@ -239,6 +413,146 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 }
 
+// Next session in the given direction the user may start, the current one if there is none
+fn next_allowed_session(session_access: &[bool], current: usize, forward: bool) -> usize {
+    let count = session_access.len();
+    (1..=count)
+        .map(|step| if forward { (current + step) % count } else { (current + count - step) % count })
+        .find(|&session| session_access[session])
+        .unwrap_or(current)
+}
+
+// Count down until the autologin, true if it was cancelled by a key press
+fn autologin_cancelled(countdown: u64, target: &AutologinTarget, status_bar_pos: Origin) -> Result<bool, Box<dyn std::error::Error>> {
+    shims::ncurses::timeout(1000);
+    let mut cancelled = false;
+    for remaining in (1..=countdown).rev() {
+        clear_line(status_bar_pos)?;
+        mvaddstr(status_bar_pos, format!("→ logging in {} to {} in {} s, press any key to cancel", target.user, target.session, remaining))?;
+        refresh()?;
+        if getch().is_ok() {
+            cancelled = true;
+            break;
+        }
+    }
+    shims::ncurses::timeout(-1);
+    clear_line(status_bar_pos)?;
+    Ok(cancelled)
+}
+
+// List the files skipped during session discovery until a key is pressed
+fn show_session_diagnostics(diagnostics: &[SessionDiagnostic]) -> Result<(), Box<dyn std::error::Error>> {
+    clear()?;
+    let mut position = Origin { y: 1, x: 2 };
+    if diagnostics.is_empty() {
+        mvaddstr(position, "All session files were read.")?;
+    } else {
+        mvaddstr(position, "Skipped session files:")?;
+    }
+    for diagnostic in diagnostics {
+        position.y += 1;
+        mvaddstr(position, diagnostic.to_string())?;
+    }
+    position.y += 2;
+    mvaddstr(position, "Press any key to return...")?;
+    getch()?;
+    clear()?;
+    Ok(())
+}
+
+// Leave ncurses and let the helper start the session, in demo mode only show what would be started
+fn hand_over_session(helper_client: &mut HelperClient, demo: bool, position: Origin, user_name: &str, command: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if demo {
+        clear_line(position)?;
+        mvaddstr(position, format!("demo: {} would start {}. Press any key to exit...", user_name, command))?;
+        getch()?;
+    }
+    clear()?;
+    refresh()?;
+    endwin()?;
+    // The helper starts the session as soon as this process exited
+    helper_client.start_session()?;
+    Ok(())
+}
+
+// Renders the PAM conversation in the greeter
+struct GreeterConversation {
+    position: Origin,
+    status_bar_pos: Origin,
+    password_prompt: String,
+    password_stars: bool,
+    // Set when PAM sent an info or error message to the status bar
+    messages_shown: bool,
+}
+
+impl GreeterConversation {
+    // Read the answer character by character straight into a locked buffer, so the input is never copied
+    fn read_answer(&mut self, message: &str, echo_on: bool) -> Result<Secret, Box<dyn std::error::Error>> {
+        // Replace the default password prompt of pam_unix by the configured one
+        let label = if !echo_on && message.trim().eq_ignore_ascii_case("password:") {
+            self.password_prompt.as_str()
+        } else {
+            message
+        };
+        clear_line(self.position)?;
+        mvaddstr(self.position, label)?;
+        let input_pos = Origin { y: self.position.y, x: getcurx(stdscr())? };
+
+        curs_set(CursorType::Visible)?;
+        let mut answer = Secret::new();
+        loop {
+            match get_wch()? {
+                CharacterResult::Key(KeyBinding::Enter) => break,
+                CharacterResult::Key(KeyBinding::Backspace) => {
+                    answer.pop_char();
+                }
+                CharacterResult::Key(_) => {}
+                CharacterResult::Character(wide_char) => match char::try_from(wide_char) {
+                    Ok('\n') | Ok('\r') => break,
+                    Ok('\x7f') | Ok('\x08') => {
+                        answer.pop_char();
+                    }
+                    Ok(ch) if !ch.is_control() => {
+                        answer.push_char(ch);
+                    }
+                    _ => {}
+                },
+            }
+
+            // Redraw the input, answers to echo on prompts (e.g. verification codes) are shown in clear text
+            clear_line(input_pos)?;
+            if echo_on {
+                mvaddstr(input_pos, String::from_utf8_lossy(answer.as_bytes()))?;
+            } else if self.password_stars {
+                mvaddstr(input_pos, "*".repeat(answer.char_count()))?;
+            }
+        }
+        curs_set(CursorType::Invisible)?;
+        Ok(answer)
+    }
+
+    fn show_message(&mut self, message: &str) -> Result<(), Box<dyn std::error::Error>> {
+        clear_line(self.status_bar_pos)?;
+        mvaddstr(self.status_bar_pos, format!("→ {}", message))?;
+        refresh()?;
+        self.messages_shown = true;
+        Ok(())
+    }
+}
+
+impl Conversation for GreeterConversation {
+    fn converse(&mut self, style: MessageStyle, message: &str) -> Option<Secret> {
+        match style {
+            MessageStyle::PromptEchoOff => self.read_answer(message, false).ok(),
+            MessageStyle::PromptEchoOn => self.read_answer(message, true).ok(),
+            MessageStyle::ErrorMsg | MessageStyle::TextInfo => {
+                self.show_message(message).ok()?;
+                Some(Secret::new())
+            }
+        }
+    }
+}
+
 fn clear_line(p0: Origin) -> Result<(), Box<dyn std::error::Error>> {
     wmove(stdscr(), p0)?; // Move cursor to the beginning of the line
     wclrtoeol(stdscr())?;      // Clear from cursor to end of line
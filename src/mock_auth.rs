@@ -0,0 +1,181 @@
+use serde::Deserialize;
+use std::{collections::HashMap, fs, io};
+
+use crate::auth_user::{AuthError, AuthSession, Authenticator, Conversation, MessageStyle};
+use crate::user::User;
+
+// Message sent to the conversation, prompts fail the login if the answer doesn't match
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScriptedMessage {
+    pub style: MessageStyle,
+    pub text: String,
+    #[serde(default)]
+    pub answer: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountState {
+    #[default]
+    Ok,
+    Locked,
+    Expired,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MockUser {
+    pub name: String,
+    pub password: String,
+    #[serde(default = "default_uid")]
+    pub uid: usize,
+    #[serde(default = "default_shell")]
+    pub shell: String,
+    #[serde(default)]
+    pub account: AccountState,
+    #[serde(default)]
+    pub password_expired: bool,
+    // Sent after the password prompt, e.g. a verification code prompt
+    #[serde(default)]
+    pub messages: Vec<ScriptedMessage>,
+    // Sent during the account check, e.g. a password expiry warning
+    #[serde(default)]
+    pub account_messages: Vec<ScriptedMessage>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+}
+
+fn default_uid() -> usize { 1000 }
+fn default_shell() -> String { "/bin/bash".to_string() }
+
+#[derive(Debug, Deserialize)]
+struct MockFixture {
+    #[serde(default)]
+    users: Vec<MockUser>,
+}
+
+// In-process authenticator driven by a TOML fixture, used for the demo mode and for testing the greeter
+pub struct MockAuthenticator {
+    users: Vec<MockUser>,
+}
+
+impl MockAuthenticator {
+    pub fn from_file(fixture_path: &str) -> io::Result<MockAuthenticator> {
+        let content = fs::read_to_string(fixture_path)?;
+        let fixture: MockFixture = toml::from_str(&content)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Failed to parse mock fixture: {}", e)))?;
+        Ok(MockAuthenticator { users: fixture.users })
+    }
+
+    // Users of the fixture as passwd entries for the user selection
+    pub fn users(&self) -> Vec<User> {
+        self.users
+            .iter()
+            .map(|mock_user| User {
+                name: mock_user.name.clone(),
+                uid: mock_user.uid,
+                gid: mock_user.uid,
+                gecos: String::new(),
+                homedir: format!("/home/{}", mock_user.name),
+                shell: mock_user.shell.clone(),
+            })
+            .collect()
+    }
+}
+
+impl Authenticator for MockAuthenticator {
+    fn start<'a>(&self, _service: &str, username: &str, _tty_path: &str, conv: Box<dyn Conversation + 'a>) -> Result<Box<dyn AuthSession + 'a>, AuthError> {
+        Ok(Box::new(MockSession {
+            user: self.users.iter().find(|mock_user| mock_user.name == username).cloned(),
+            conversation: conv,
+        }))
+    }
+}
+
+struct MockSession<'a> {
+    user: Option<MockUser>,
+    conversation: Box<dyn Conversation + 'a>,
+}
+
+impl MockSession<'_> {
+    // Ask a prompt, Ok(true) if the answer matches the expected one
+    fn ask(&mut self, style: MessageStyle, text: &str, expected: Option<&str>) -> Result<bool, AuthError> {
+        let answer = self.conversation.converse(style, text).ok_or(AuthError::ConversationAborted)?;
+        Ok(expected.is_none_or(|expected| answer.eq_bytes(expected.as_bytes())))
+    }
+
+    // Send scripted messages, Ok(false) if a prompt was answered wrong
+    fn run_script(&mut self, script: &[ScriptedMessage]) -> Result<bool, AuthError> {
+        let mut all_correct = true;
+        for message in script {
+            all_correct &= self.ask(message.style, &message.text, message.answer.as_deref())?;
+        }
+        Ok(all_correct)
+    }
+}
+
+impl AuthSession for MockSession<'_> {
+    fn notify(&mut self, style: MessageStyle, message: &str) {
+        self.conversation.converse(style, message);
+    }
+
+    fn authenticate(&mut self) -> Result<(), AuthError> {
+        // Ask for the password even for unknown users, like PAM does
+        let password = self.user.as_ref().map(|mock_user| mock_user.password.clone());
+        let password_correct = self.ask(MessageStyle::PromptEchoOff, "Password: ", Some(password.as_deref().unwrap_or("")))?;
+        let Some(script) = self.user.as_ref().map(|mock_user| mock_user.messages.clone()) else {
+            return Err(AuthError::UserUnknown);
+        };
+        let script_correct = self.run_script(&script)?;
+
+        if password_correct && script_correct {
+            Ok(())
+        } else {
+            Err(AuthError::AuthFailed)
+        }
+    }
+
+    fn account_check(&mut self) -> Result<(), AuthError> {
+        let mock_user = self.user.clone().ok_or(AuthError::UserUnknown)?;
+        self.run_script(&mock_user.account_messages)?;
+        match mock_user.account {
+            AccountState::Locked => Err(AuthError::PermissionDenied),
+            AccountState::Expired => Err(AuthError::AccountExpired),
+            AccountState::Ok if mock_user.password_expired => Err(AuthError::PasswordExpired),
+            AccountState::Ok => Ok(()),
+        }
+    }
+
+    fn change_expired_authtok(&mut self) -> Result<(), AuthError> {
+        let old_password = self.user.as_ref().map(|mock_user| mock_user.password.clone()).ok_or(AuthError::UserUnknown)?;
+        if !self.ask(MessageStyle::PromptEchoOff, "Current password: ", Some(&old_password))? {
+            return Err(AuthError::PasswordChangeFailed);
+        }
+        let new_password = self.conversation.converse(MessageStyle::PromptEchoOff, "New password: ").ok_or(AuthError::ConversationAborted)?;
+        if new_password.is_empty() || new_password.eq_bytes(old_password.as_bytes()) {
+            return Err(AuthError::PasswordChangeFailed);
+        }
+        let retyped_password = self.conversation.converse(MessageStyle::PromptEchoOff, "Retype new password: ").ok_or(AuthError::ConversationAborted)?;
+        if !retyped_password.eq_bytes(new_password.as_bytes()) {
+            return Err(AuthError::PasswordChangeFailed);
+        }
+        // Mock passwords are plain strings anyway
+        if let Some(mock_user) = self.user.as_mut() {
+            mock_user.password = String::from_utf8_lossy(new_password.as_bytes()).into_owned();
+            mock_user.password_expired = false;
+        }
+        Ok(())
+    }
+
+    fn open_session(&mut self) -> Result<(), AuthError> {
+        Ok(())
+    }
+
+    fn env_list(&mut self) -> Vec<(String, String)> {
+        self.user
+            .as_ref()
+            .map(|mock_user| mock_user.env.iter().map(|(name, value)| (name.clone(), value.clone())).collect())
+            .unwrap_or_default()
+    }
+
+    fn close_session(&mut self) {}
+}
@@ -1,7 +1,7 @@
 use std::process::{Command};
 
 /// Enable or disable Num Lock on TTY
-pub(crate) fn set_num_lock_tty(enable: bool) -> std::io::Result<()> {
+pub fn set_num_lock_tty(enable: bool) -> std::io::Result<()> {
     let arg = if enable { "+num" } else { "-num" };
 
     // Execute the `setleds` command with the correct argument
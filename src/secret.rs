@@ -0,0 +1,150 @@
+use std::{alloc::{self, Layout}, io::{self, Read}, os::raw::{c_char, c_void}, ptr::NonNull, slice};
+
+// One page, so locking and unlocking a secret doesn't affect other allocations
+const SECRET_SIZE: usize = 4096;
+
+// Buffer for passwords and other answers to PAM prompts.
+// The memory is locked so it is never swapped out and wiped when dropped.
+// The buffer never grows, so no copies are left behind by reallocations.
+// Secret intentionally implements neither Debug nor Clone.
+pub struct Secret {
+    ptr: NonNull<u8>,
+    len: usize,
+    locked: bool,
+}
+
+fn layout() -> Layout {
+    Layout::from_size_align(SECRET_SIZE, SECRET_SIZE).expect("invalid secret layout")
+}
+
+impl Secret {
+    pub fn new() -> Secret {
+        let layout = layout();
+        let ptr = match NonNull::new(unsafe { alloc::alloc_zeroed(layout) }) {
+            Some(ptr) => ptr,
+            None => alloc::handle_alloc_error(layout),
+        };
+        // Locking fails without CAP_IPC_LOCK if RLIMIT_MEMLOCK is exhausted, the secret is still wiped then
+        let locked = unsafe { libc::mlock(ptr.as_ptr() as *const c_void, SECRET_SIZE) } == 0;
+        Secret { ptr, len: 0, locked }
+    }
+
+    // Copy a string into a new secret, only meant for values that are no secrets of nclogin (e.g. mock fixtures)
+    pub fn copy_from_str(value: &str) -> Secret {
+        let mut secret = Secret::new();
+        secret.push_bytes(value.as_bytes());
+        secret
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Number of UTF-8 characters, e.g. to show one star per character
+    pub fn char_count(&self) -> usize {
+        self.as_bytes().iter().filter(|byte| *byte & 0xC0 != 0x80).count()
+    }
+
+    // Append bytes, false if they don't fit (one byte is kept for the NUL terminator)
+    pub fn push_bytes(&mut self, bytes: &[u8]) -> bool {
+        if self.len + bytes.len() >= SECRET_SIZE {
+            return false;
+        }
+        unsafe {
+            self.ptr.as_ptr().add(self.len).copy_from_nonoverlapping(bytes.as_ptr(), bytes.len());
+        }
+        self.len += bytes.len();
+        true
+    }
+
+    pub fn push_char(&mut self, ch: char) -> bool {
+        let mut buf = [0u8; 4];
+        let pushed = self.push_bytes(ch.encode_utf8(&mut buf).as_bytes());
+        wipe(buf.as_mut_ptr(), buf.len());
+        pushed
+    }
+
+    // Append exactly len bytes read from the stream, without any intermediate buffer
+    pub fn read_from(&mut self, stream: &mut impl Read, len: usize) -> io::Result<()> {
+        if self.len + len >= SECRET_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "secret too long"));
+        }
+        let buf = unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr().add(self.len), len) };
+        stream.read_exact(buf)?;
+        self.len += len;
+        Ok(())
+    }
+
+    // Remove the last UTF-8 character, false if the secret is empty
+    pub fn pop_char(&mut self) -> bool {
+        if self.len == 0 {
+            return false;
+        }
+        while self.len > 0 {
+            self.len -= 1;
+            let byte = unsafe { *self.ptr.as_ptr().add(self.len) };
+            wipe(unsafe { self.ptr.as_ptr().add(self.len) }, 1);
+            // Stop at the first byte of the character
+            if byte & 0xC0 != 0x80 {
+                break;
+            }
+        }
+        true
+    }
+
+    // Compare without leaking the position of the first difference through timing
+    pub fn eq_bytes(&self, other: &[u8]) -> bool {
+        let bytes = self.as_bytes();
+        if bytes.len() != other.len() {
+            return false;
+        }
+        bytes.iter().zip(other).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+    }
+
+    // Copy into a NUL terminated buffer from the C allocator, which PAM takes ownership of and frees.
+    // Returns NULL if the allocation fails.
+    pub fn to_pam_response(&self) -> *mut c_char {
+        unsafe {
+            let response = libc::malloc(self.len + 1) as *mut u8;
+            if response.is_null() {
+                return std::ptr::null_mut();
+            }
+            response.copy_from_nonoverlapping(self.ptr.as_ptr(), self.len);
+            *response.add(self.len) = 0;
+            response as *mut c_char
+        }
+    }
+}
+
+impl Default for Secret {
+    fn default() -> Self {
+        Secret::new()
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        wipe(self.ptr.as_ptr(), SECRET_SIZE);
+        unsafe {
+            if self.locked {
+                libc::munlock(self.ptr.as_ptr() as *const c_void, SECRET_SIZE);
+            }
+            alloc::dealloc(self.ptr.as_ptr(), layout());
+        }
+    }
+}
+
+// Zero memory in a way the compiler can't optimize away
+fn wipe(ptr: *mut u8, len: usize) {
+    unsafe {
+        libc::explicit_bzero(ptr as *mut c_void, len);
+    }
+}
@@ -1,8 +1,11 @@
-use std::{fmt, fs, io::{self, BufRead, Write}, path::Path};
+use std::{collections::{HashMap, HashSet}, fmt, fs, io::{self, BufRead}, path::Path};
 use serde::{Serialize, Deserialize};
 use toml;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::desktop_entry::DesktopEntry;
+use crate::settings::{RuleAction, SessionRule};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum SessionType {
     X11,
@@ -20,140 +23,335 @@ impl fmt::Display for SessionType {
         write!(f, "{}", lowercase_str)
     }
 }
+// How the session command is started
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LaunchMode {
+    // Like login(1) with "-" in front of argv[0], so shells read /etc/profile and ~/.profile
+    Login,
+    // Shell started with -i
+    Interactive,
+    // Command started as it is
+    Exec,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Session {
+    // Stable id for sessions.toml and the last selection: type/file name for discovered sessions,
+    // set by the admin for added sessions
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub id: String,
     pub name: String,
+    // Program of the session
     pub cmd: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
     pub session_type: SessionType,
+    // Shown under the session in the greeter, from Comment of the desktop file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    // Desktop names for XDG_CURRENT_DESKTOP, from DesktopNames of the desktop file
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub desktop_names: Vec<String>,
+    // Defaults to login for shells and exec for graphical sessions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub launch_mode: Option<LaunchMode>,
+    // Directory the session is started in, relative to the home directory. Defaults to the home directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<String>,
+    // Overrides the wrapper of the session type, an empty string disables it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wrapper: Option<String>,
+    // Extra environment variables, these win over the ones set by nclogin and PAM
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
 }
 
-#[derive(Serialize, Deserialize)]
-struct SessionList {
-    sessions: Vec<Session>,
-}
+impl Session {
+    // Sessions are referred to by id, names are accepted for files of older versions
+    pub fn matches(&self, id_or_name: &str) -> bool {
+        self.id == id_or_name || self.name == id_or_name
+    }
 
-// Function to read sessions from TOML or parse & save if missing
-pub fn get_sessions(toml_path: &str, shell_file: &str, x11_folder: &str, wayland_folder: &str) -> io::Result<Vec<Session>> {
-    // Try to load from TOML
-    if let Ok(sessions) = parse_sessions_from_toml(toml_path) {
-        println!("Loaded sessions from TOML.");
-        return Ok(sessions);
+    pub fn launch_mode(&self) -> LaunchMode {
+        self.launch_mode.unwrap_or(match self.session_type {
+            SessionType::Shell => LaunchMode::Login,
+            SessionType::X11 | SessionType::Wayland => LaunchMode::Exec,
+        })
     }
+}
 
-    println!("TOML file not found. Parsing sessions...");
+// Check the session rules, the first rule that applies to the user and the session decides.
+// Sessions no rule applies to are allowed.
+pub fn session_allowed(rules: &[SessionRule], user_name: &str, groups: &[String], session: &Session) -> bool {
+    let session_type = session.session_type.to_string();
+    let applies = |rule: &&SessionRule| {
+        let user_matches = (rule.users.is_empty() && rule.groups.is_empty())
+            || rule.users.iter().any(|user| user == user_name)
+            || rule.groups.iter().any(|group| groups.contains(group));
+        user_matches
+            && (rule.session_types.is_empty() || rule.session_types.contains(&session_type))
+            && (rule.sessions.is_empty() || rule.sessions.contains(&session.id))
+    };
+    rules.iter().find(applies).is_none_or(|rule| rule.action == RuleAction::Allow)
+}
 
-    // If TOML is missing, parse from system files
-    let all_sessions = parse_all_sessions(shell_file,x11_folder,wayland_folder)?;
-    // Save parsed sessions to TOML for future use
-    // Attempt to save parsed sessions to TOML, log an error if it fails
-    if let Err(e) = save_sessions_to_toml(toml_path, &all_sessions) {
-        eprintln!("Warning: Failed to save sessions to TOML: {}", e);
-    }
-    Ok(all_sessions)
+// A file skipped during session discovery and why
+#[derive(Debug, Clone)]
+pub struct SessionDiagnostic {
+    pub file: String,
+    pub reason: String,
 }
 
+impl SessionDiagnostic {
+    fn new(file: &Path, reason: impl fmt::Display) -> SessionDiagnostic {
+        SessionDiagnostic { file: file.display().to_string(), reason: reason.to_string() }
+    }
+}
 
-fn parse_sessions_from_toml(toml_path: &str) -> io::Result<Vec<Session>> {
-    if !Path::new(toml_path).exists() {
-        return Err(io::Error::new(io::ErrorKind::NotFound, "TOML file not found"));
+impl fmt::Display for SessionDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.file, self.reason)
     }
+}
 
-    let toml_content = fs::read_to_string(toml_path)?;
-    let parsed: SessionList = toml::from_str(&toml_content)
-        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Failed to parse TOML"))?;
+// sessions.toml, written by the admin. Discovered sessions aren't stored, they are found again on every start.
+#[derive(Default, Deserialize)]
+struct SessionList {
+    // Ids of sessions shown first in this order, the others follow in the order they were found
+    #[serde(default)]
+    order: Vec<String>,
+    // Sessions added by the admin
+    #[serde(default)]
+    sessions: Vec<Session>,
+    // Changes to discovered or added sessions by id
+    #[serde(default)]
+    overrides: HashMap<String, SessionOverride>,
+}
+
+#[derive(Default, Deserialize)]
+struct SessionOverride {
+    // Name shown in the greeter
+    name: Option<String>,
+    // Hidden sessions aren't shown
+    #[serde(default)]
+    hidden: bool,
+    description: Option<String>,
+    launch_mode: Option<LaunchMode>,
+    // Replaces the arguments of the session
+    args: Option<Vec<String>>,
+    // Added to the environment of the session, replacing variables of the same name
+    #[serde(default)]
+    env: HashMap<String, String>,
+    working_dir: Option<String>,
+    wrapper: Option<String>,
+}
 
-    Ok(parsed.sessions)
+impl SessionOverride {
+    // Fields that aren't set keep the values of the discovered session
+    fn apply(&self, session: &mut Session) {
+        if let Some(name) = &self.name {
+            session.name = name.clone();
+        }
+        if self.description.is_some() {
+            session.description = self.description.clone();
+        }
+        if self.launch_mode.is_some() {
+            session.launch_mode = self.launch_mode;
+        }
+        if let Some(args) = &self.args {
+            session.args = args.clone();
+        }
+        session.env.extend(self.env.clone());
+        if self.working_dir.is_some() {
+            session.working_dir = self.working_dir.clone();
+        }
+        if self.wrapper.is_some() {
+            session.wrapper = self.wrapper.clone();
+        }
+    }
 }
 
-fn save_sessions_to_toml(toml_path: &str, sessions: &[Session]) -> io::Result<()> {
-    let session_list = SessionList {
-        sessions: sessions.to_vec(),
+// Discover the sessions installed on the system and apply the entries and overrides of sessions.toml
+// Files that can't be used are skipped and returned as diagnostics
+pub fn get_sessions(toml_path: &str, shell_file: &str, x11_dirs: &[String], wayland_dirs: &[String]) -> (Vec<Session>, Vec<SessionDiagnostic>) {
+    let (mut sessions, mut diagnostics) = discover_sessions(shell_file, x11_dirs, wayland_dirs);
+
+    let session_list = match parse_sessions_from_toml(toml_path) {
+        Ok(session_list) => session_list,
+        Err(e) => {
+            diagnostics.push(SessionDiagnostic::new(Path::new(toml_path), e));
+            SessionList::default()
+        }
     };
-    let toml_string = toml::to_string(&session_list)
-        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Failed to serialize to TOML"))?;
 
-    if let Some(parent) = Path::new(toml_path).parent() {
-        fs::create_dir_all(parent)?;
+    for session in session_list.sessions {
+        // Files of older versions list the discovered sessions without an id, these are found again
+        // Ones that aren't installed anymore are reported instead of being kept with their old command line
+        if session.id.is_empty() {
+            let discovered_id = format!("{}/{}", session.session_type, session.name);
+            if !sessions.iter().any(|known| known.id == discovered_id) {
+                diagnostics.push(SessionDiagnostic::new(Path::new(toml_path), format!(
+                    "session {} has no id and isn't installed anymore, add an id to keep it", session.name)));
+            }
+            continue;
+        }
+        sessions.push(session);
+    }
+
+    for session in sessions.iter_mut() {
+        if let Some(session_override) = session_list.overrides.get(&session.id) {
+            session_override.apply(session);
+        }
+    }
+    sessions.retain(|session| !session_list.overrides.get(&session.id).is_some_and(|o| o.hidden));
+
+    // A stable sort keeps the order of the sessions that aren't listed
+    let position = |session: &Session| session_list.order.iter().position(|id| *id == session.id).unwrap_or(usize::MAX);
+    sessions.sort_by_key(position);
+    (sessions, diagnostics)
+}
+
+// A missing file is the same as an empty one
+fn parse_sessions_from_toml(toml_path: &str) -> io::Result<SessionList> {
+    if !Path::new(toml_path).exists() {
+        return Ok(SessionList::default());
     }
 
-    let mut file = fs::File::create(toml_path)?;
-    file.write_all(toml_string.as_bytes())?;
-    Ok(())
+    let toml_content = fs::read_to_string(toml_path)?;
+    toml::from_str(&toml_content)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Failed to parse TOML: {}", e)))
 }
 
-fn parse_shell_sessions(path: &str) -> io::Result<Vec<Session>> {
+fn parse_shell_sessions(path: &str, diagnostics: &mut Vec<SessionDiagnostic>) -> Vec<Session> {
     let mut sessions = Vec::new();
-    let file = fs::File::open(path)?;
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            diagnostics.push(SessionDiagnostic::new(Path::new(path), e));
+            return sessions;
+        }
+    };
     let reader = io::BufReader::new(file);
 
     for line in reader.lines() {
-        let line = line?;
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                diagnostics.push(SessionDiagnostic::new(Path::new(path), e));
+                break;
+            }
+        };
         if line.starts_with("/") {
             let session_name = line.split('/').last().unwrap_or("Unknown").to_string();
+            // /etc/shells often lists a shell in /bin and /usr/bin, the first one is kept
+            let id = format!("{}/{}", SessionType::Shell, session_name);
+            if sessions.iter().any(|session: &Session| session.id == id) {
+                continue;
+            }
             sessions.push(Session {
+                id,
                 name: session_name,
                 cmd: line,
+                args: Vec::new(),
                 session_type: SessionType::Shell,
+                description: None,
+                desktop_names: Vec::new(),
+                launch_mode: None,
+                working_dir: None,
+                wrapper: None,
+                env: HashMap::new(),
             });
         }
     }
 
-    Ok(sessions)
+    sessions
 }
 
-fn parse_desktop_file(file_path: &Path) -> io::Result<String> {
-    let file = fs::File::open(file_path)?;
-    let reader = io::BufReader::new(file);
-
-    for line in reader.lines() {
-        let line = line?;
-        if line.starts_with("Exec=") {
-            return Ok(line.trim_start_matches("Exec=").to_string());
-        }
+// Session of a desktop file, None if it is hidden or its program isn't installed
+fn parse_desktop_file(file_path: &Path, session_type: SessionType) -> io::Result<Option<Session>> {
+    let entry = DesktopEntry::from_file(file_path)?;
+    if entry.is_hidden() || !entry.try_exec_found() {
+        return Ok(None);
     }
-    Err(io::Error::new(io::ErrorKind::NotFound, "Exec field not found"))
+
+    let mut argv = entry.exec_argv()?;
+    let cmd = argv.remove(0);
+    let file_stem = file_path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    // Name and comment are looked up in the locale of messages, the file name is used if the entry has no name
+    let name = entry.name().unwrap_or_else(|| file_stem.clone());
+    Ok(Some(Session {
+        id: format!("{}/{}", session_type, file_stem),
+        name,
+        cmd,
+        args: argv,
+        session_type,
+        description: entry.comment(),
+        desktop_names: entry.desktop_names(),
+        launch_mode: None,
+        working_dir: None,
+        wrapper: None,
+        env: HashMap::new(),
+    }))
 }
 
-fn parse_sessions_from_directory(directory: &str, session_type: SessionType) -> io::Result<Vec<Session>> {
+// Desktop file ids in `seen` were found in an earlier directory and are skipped
+fn parse_sessions_from_directory(directory: &str, session_type: SessionType, seen: &mut HashSet<String>, diagnostics: &mut Vec<SessionDiagnostic>) -> Vec<Session> {
     let mut sessions = Vec::new();
 
     if Path::new(directory).exists() {
-        for entry in fs::read_dir(directory)? {
-            let entry = entry?;
-            if entry.path().extension() == Some(std::ffi::OsStr::new("desktop")) {
-                let mut session_name = entry.file_name().to_string_lossy().to_string();
-                let cmd = parse_desktop_file(&entry.path())?;
-                if let Some(stripped_name) = session_name.strip_suffix(".desktop") {
-                    session_name = stripped_name.to_string();
-                }
-                sessions.push(Session {
-                    name: session_name,
-                    cmd,
-                    session_type: session_type.clone(),
-                });
+        let entries = match fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(e) => {
+                diagnostics.push(SessionDiagnostic::new(Path::new(directory), e));
+                return sessions;
+            }
+        };
+        let mut paths = Vec::new();
+        for entry in entries {
+            match entry {
+                Ok(entry) => paths.push(entry.path()),
+                Err(e) => diagnostics.push(SessionDiagnostic::new(Path::new(directory), e)),
+            }
+        }
+        // read_dir has no order, sort to show the sessions in the same order on every start
+        paths.sort();
+        for path in paths {
+            if path.extension() != Some(std::ffi::OsStr::new("desktop")) {
+                continue;
+            }
+            // The file of an earlier directory wins even if it is hidden or broken, so it can mask a later one
+            let desktop_file_id = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+            if !seen.insert(desktop_file_id) {
+                continue;
+            }
+            // A broken file only costs its own session
+            match parse_desktop_file(&path, session_type.clone()) {
+                Ok(Some(session)) => sessions.push(session),
+                Ok(None) => {}
+                Err(e) => diagnostics.push(SessionDiagnostic::new(&path, e)),
             }
         }
     }
 
-    Ok(sessions)
+    sessions
 }
 
-fn parse_all_sessions(shell_file: &str, x11_folder: &str, wayland_folder: &str) -> io::Result<Vec<Session>> {
-    let mut all_sessions = Vec::new();
-
-    all_sessions.extend(parse_shell_sessions(shell_file)?);
-    all_sessions.extend(parse_sessions_from_directory(x11_folder, SessionType::X11)?);
-    all_sessions.extend(parse_sessions_from_directory(wayland_folder, SessionType::Wayland)?);
-    Ok(all_sessions)
+// Sessions of all directories of a session type, earlier directories take precedence
+fn parse_sessions_from_directories(directories: &[String], session_type: SessionType, diagnostics: &mut Vec<SessionDiagnostic>) -> Vec<Session> {
+    let mut seen = HashSet::new();
+    directories.iter()
+        .flat_map(|directory| parse_sessions_from_directory(directory, session_type.clone(), &mut seen, diagnostics))
+        .collect()
 }
 
-pub fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let toml_path = "/etc/nclogin/sessions.toml";
-    let sessions = get_sessions(toml_path, "/etc/shells", "/usr/share/xsessions", "/usr/share/wayland-sessions")?;
-
-    for session in &sessions {
-        println!("{:?}", session);
-    }
+// Parse the sessions from the shells file and the session directories, without sessions.toml
+fn discover_sessions(shell_file: &str, x11_dirs: &[String], wayland_dirs: &[String]) -> (Vec<Session>, Vec<SessionDiagnostic>) {
+    let mut all_sessions = Vec::new();
+    let mut diagnostics = Vec::new();
 
-    Ok(())
+    all_sessions.extend(parse_shell_sessions(shell_file, &mut diagnostics));
+    all_sessions.extend(parse_sessions_from_directories(x11_dirs, SessionType::X11, &mut diagnostics));
+    all_sessions.extend(parse_sessions_from_directories(wayland_dirs, SessionType::Wayland, &mut diagnostics));
+    (all_sessions, diagnostics)
 }
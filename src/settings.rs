@@ -1,5 +1,5 @@
-use serde::{Deserialize, Serialize};
-use std::{fs::{File, create_dir_all, read_to_string}, io::Write, path::Path};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{collections::HashMap, env, fs::{File, create_dir_all, read_to_string}, io::Write, path::Path};
 use toml;
 use ncursesw::normal::{Color, ColorPalette};
 
@@ -21,6 +21,15 @@ pub struct Settings {
     pub top_bar: TopBar,
     #[serde(default)]
     pub bottom_bar: BottomBar,
+    #[serde(default)]
+    pub login_throttle: LoginThrottle,
+    #[serde(default)]
+    pub accounting: Accounting,
+    #[serde(default)]
+    pub autologin: Autologin,
+    // Who may start which sessions, the first matching rule decides. Sessions no rule matches are allowed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub session_rules: Vec<SessionRule>,
 }
 
 impl Default for Settings {
@@ -34,6 +43,10 @@ impl Default for Settings {
             colors: ColorsStruct::default(),
             top_bar: TopBar::default(),
             bottom_bar: BottomBar::default(),
+            login_throttle: LoginThrottle::default(),
+            accounting: Accounting::default(),
+            autologin: Autologin::default(),
+            session_rules: Vec::new(),
         }
     }
 }
@@ -46,20 +59,48 @@ pub struct LoginBehaviour {
     pub user_file: String,
     #[serde(default = "default_shells_file")]
     pub shells_file : String,
-    #[serde(default = "default_x11_session_folder")]
-    pub x11_session_folder: String,
-    #[serde(default = "default_wayland_session_folder")]
-    pub wayland_session_folder: String,
+    // Session directories searched before the ones in XDG_DATA_DIRS, earlier ones win.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub x11_session_dirs: Vec<String>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub wayland_session_dirs: Vec<String>,
+    // Search xsessions and wayland-sessions in the directories of XDG_DATA_DIRS
+    #[serde(default = "default_use_xdg_data_dirs")]
+    pub use_xdg_data_dirs: bool,
+    // Single session directory of older versions, searched after the XDG_DATA_DIRS ones.
+    // Config files written by them hold the old default, which XDG_DATA_DIRS already covers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x11_session_folder: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wayland_session_folder: Option<String>,
     #[serde(default = "default_session_file")]
     pub session_file: String,
+    // Single last user and session of older versions, only read until the state file exists
     #[serde(default = "default_default_selection_file")]
     pub default_selection_file: String,
+    // Last session per user and last user per tty
+    #[serde(default = "default_state_file")]
+    pub state_file: String,
     #[serde(default = "default_last_to_default_selection")]
     pub write_last_to_default_selection: bool,
     #[serde(default = "default_include_root_user")]
     pub include_root_user: bool,
     #[serde(default = "default_activate_num_lock")]
     pub activate_num_lock: bool,
+    #[serde(default = "default_pam_service")]
+    pub pam_service: String,
+    // Unprivileged user the greeter UI runs as
+    #[serde(default = "default_greeter_user")]
+    pub greeter_user: String,
+    // Script graphical sessions are started with, it gets the session command as arguments. Empty disables it.
+    #[serde(default = "default_session_wrapper")]
+    pub session_wrapper: String,
+    // PAM service per session type (x11, wayland, tty), overrides pam_service
+    #[serde(default)]
+    pub pam_service_overrides: HashMap<String, String>,
+    // Wrapper per session type (x11, wayland, tty), overrides session_wrapper
+    #[serde(default)]
+    pub session_wrapper_overrides: HashMap<String, String>,
 }
 
 impl Default for LoginBehaviour {
@@ -68,17 +109,97 @@ impl Default for LoginBehaviour {
             min_uid: default_min_uid(),
             user_file: default_user_file(),
             shells_file: default_shells_file(),
-            x11_session_folder: default_x11_session_folder(),
-            wayland_session_folder: default_wayland_session_folder(),
+            x11_session_dirs: Vec::new(),
+            wayland_session_dirs: Vec::new(),
+            use_xdg_data_dirs: default_use_xdg_data_dirs(),
+            x11_session_folder: None,
+            wayland_session_folder: None,
             session_file: default_session_file(),
             default_selection_file: default_default_selection_file(),
+            state_file: default_state_file(),
             write_last_to_default_selection: default_last_to_default_selection(),
             include_root_user: default_include_root_user(),
             activate_num_lock: default_activate_num_lock(),
+            pam_service: default_pam_service(),
+            greeter_user: default_greeter_user(),
+            session_wrapper: default_session_wrapper(),
+            pam_service_overrides: HashMap::new(),
+            session_wrapper_overrides: HashMap::new(),
+        }
+    }
+}
+
+impl LoginBehaviour {
+    // Get the PAM service used to log in to a session of the given type
+    pub fn pam_service_for(&self, session_type: &str) -> &str {
+        self.pam_service_overrides
+            .get(session_type)
+            .unwrap_or(&self.pam_service)
+    }
+
+    // Get the wrapper for sessions of the given type, shells are login shells and aren't wrapped by default
+    pub fn session_wrapper_for(&self, session_type: &str) -> Option<&str> {
+        let wrapper = match self.session_wrapper_overrides.get(session_type) {
+            Some(wrapper) => wrapper.as_str(),
+            None if session_type == "tty" => "",
+            None => self.session_wrapper.as_str(),
+        };
+        (!wrapper.is_empty()).then_some(wrapper)
+    }
+
+    // Directories searched for X11 sessions, earlier ones take precedence
+    pub fn x11_session_dirs(&self) -> Vec<String> {
+        self.session_dirs(&self.x11_session_dirs, self.x11_session_folder.as_deref(), "xsessions")
+    }
+
+    // Directories searched for Wayland sessions, earlier ones take precedence
+    pub fn wayland_session_dirs(&self) -> Vec<String> {
+        self.session_dirs(&self.wayland_session_dirs, self.wayland_session_folder.as_deref(), "wayland-sessions")
+    }
+
+    fn session_dirs(&self, extra_dirs: &[String], legacy_dir: Option<&str>, subdir: &str) -> Vec<String> {
+        let mut dirs: Vec<String> = extra_dirs.to_vec();
+        if self.use_xdg_data_dirs {
+            dirs.extend(xdg_data_dirs().iter().map(|data_dir| format!("{}/{}", data_dir.trim_end_matches('/'), subdir)));
         }
+        dirs.extend(legacy_dir.map(String::from));
+        // A directory listed twice would only be searched again, like the old default folder in XDG_DATA_DIRS
+        let mut seen = Vec::new();
+        dirs.retain(|dir| {
+            let dir = dir.trim_end_matches('/').to_string();
+            let first = !seen.contains(&dir);
+            seen.push(dir);
+            first
+        });
+        dirs
     }
 }
 
+// XDG_DATA_DIRS or its default from the XDG Base Directory Specification
+fn xdg_data_dirs() -> Vec<String> {
+    let value = env::var("XDG_DATA_DIRS").unwrap_or_default();
+    let dirs: Vec<String> = value.split(':').filter(|dir| !dir.is_empty()).map(String::from).collect();
+    if dirs.is_empty() {
+        vec!["/usr/local/share".to_string(), "/usr/share".to_string()]
+    } else {
+        dirs
+    }
+}
+
+// A list of strings or a single string
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(dir) => vec![dir],
+        OneOrMany::Many(dirs) => dirs,
+    })
+}
+
 fn default_min_uid() -> usize {
     1000
 }
@@ -88,12 +209,14 @@ fn default_user_file() -> String {
 fn default_shells_file() -> String {
     "/etc/shells".to_string()
 }
-fn default_x11_session_folder() -> String { "/usr/share/xsessions".to_string() }
-fn default_wayland_session_folder() -> String { "/usr/share/wayland-sessions".to_string() }
+fn default_use_xdg_data_dirs() -> bool { true }
 fn default_session_file() -> String { "/etc/nclogin/sessions.toml".to_string() }
 fn default_default_selection_file() -> String {
     "/etc/nclogin/default".to_string()
 }
+fn default_state_file() -> String {
+    "/var/lib/nclogin/state.toml".to_string()
+}
 fn default_last_to_default_selection() -> bool {
     true
 }
@@ -103,6 +226,15 @@ fn default_include_root_user() -> bool {
 fn default_activate_num_lock() -> bool {
     true
 }
+fn default_pam_service() -> String {
+    "login".to_string()
+}
+fn default_greeter_user() -> String {
+    "nobody".to_string()
+}
+fn default_session_wrapper() -> String {
+    "/etc/nclogin/session-wrapper".to_string()
+}
 
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -323,6 +455,137 @@ fn default_bottom_text_bar_color() -> String {
     "white".to_string()
 }
 
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LoginThrottle {
+    #[serde(default = "default_throttle_enabled")]
+    pub throttle_enabled: bool,
+    // Delay after the first failure in seconds, doubled with every further failure
+    #[serde(default = "default_base_delay")]
+    pub base_delay: u64,
+    #[serde(default = "default_max_delay")]
+    pub max_delay: u64,
+    // Consecutive failures until the lockout, 0 disables the lockout
+    #[serde(default = "default_lockout_threshold")]
+    pub lockout_threshold: u32,
+    #[serde(default = "default_lockout_duration")]
+    pub lockout_duration: u64,
+    #[serde(default = "default_state_dir")]
+    pub state_dir: String,
+}
+
+impl Default for LoginThrottle {
+    fn default() -> Self {
+        LoginThrottle {
+            throttle_enabled: default_throttle_enabled(),
+            base_delay: default_base_delay(),
+            max_delay: default_max_delay(),
+            lockout_threshold: default_lockout_threshold(),
+            lockout_duration: default_lockout_duration(),
+            state_dir: default_state_dir(),
+        }
+    }
+}
+
+fn default_throttle_enabled() -> bool { true }
+fn default_base_delay() -> u64 { 2 }
+fn default_max_delay() -> u64 { 60 }
+fn default_lockout_threshold() -> u32 { 10 }
+fn default_lockout_duration() -> u64 { 300 }
+fn default_state_dir() -> String { "/var/lib/nclogin".to_string() }
+
+// Login records for who, last, lastb and lastlog
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Accounting {
+    #[serde(default = "default_utmp_file")]
+    pub utmp_file: String,
+    #[serde(default = "default_wtmp_file")]
+    pub wtmp_file: String,
+    #[serde(default = "default_btmp_file")]
+    pub btmp_file: String,
+    #[serde(default = "default_lastlog_file")]
+    pub lastlog_file: String,
+}
+
+impl Default for Accounting {
+    fn default() -> Self {
+        Accounting {
+            utmp_file: default_utmp_file(),
+            wtmp_file: default_wtmp_file(),
+            btmp_file: default_btmp_file(),
+            lastlog_file: default_lastlog_file(),
+        }
+    }
+}
+
+fn default_utmp_file() -> String { "/var/run/utmp".to_string() }
+fn default_wtmp_file() -> String { "/var/log/wtmp".to_string() }
+fn default_btmp_file() -> String { "/var/log/btmp".to_string() }
+fn default_lastlog_file() -> String { "/var/log/lastlog".to_string() }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AutologinTarget {
+    pub user: String,
+    // Id of the session, or its name as shown in the greeter
+    pub session: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Autologin {
+    // Seconds the countdown runs before the login, any key press cancels it
+    #[serde(default = "default_autologin_countdown")]
+    pub countdown: u64,
+    // PAM service without an auth step, the user is never asked for a password
+    #[serde(default = "default_autologin_pam_service")]
+    pub pam_service: String,
+    // Autologin target per tty name (e.g. tty1), the greeter is shown on all other ttys
+    #[serde(default)]
+    pub ttys: HashMap<String, AutologinTarget>,
+}
+
+impl Default for Autologin {
+    fn default() -> Self {
+        Autologin {
+            countdown: default_autologin_countdown(),
+            pam_service: default_autologin_pam_service(),
+            ttys: HashMap::new(),
+        }
+    }
+}
+
+impl Autologin {
+    pub fn for_tty(&self, tty_name: &str) -> Option<&AutologinTarget> {
+        self.ttys.get(tty_name)
+    }
+}
+
+fn default_autologin_countdown() -> u64 { 5 }
+fn default_autologin_pam_service() -> String { "nclogin-autologin".to_string() }
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    Allow,
+    Deny,
+}
+
+// A rule applies if every list that isn't empty matches
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SessionRule {
+    pub action: RuleAction,
+    // The listed users and the members of the listed groups, everyone if both are empty
+    #[serde(default)]
+    pub users: Vec<String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+    // Session types (x11, wayland, tty)
+    #[serde(default)]
+    pub session_types: Vec<String>,
+    // Session ids
+    #[serde(default)]
+    pub sessions: Vec<String>,
+}
+
 pub fn parse_settings(config_path: &str) -> Settings {
     let path = Path::new(config_path);
 
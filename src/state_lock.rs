@@ -0,0 +1,37 @@
+use std::{fs::{self, File, OpenOptions}, io::{self, Write}, os::unix::{fs::OpenOptionsExt, io::AsRawFd}, path::{Path, PathBuf}, process};
+
+// Lock on a file next to a state file that every tty reads and writes, held until it is dropped.
+// State files are replaced on writes, so the lock is taken on a separate file.
+pub struct StateLock {
+    _file: File,
+}
+
+impl StateLock {
+    // Shared for reading, exclusive for changing the state
+    pub fn acquire(path: &Path, exclusive: bool) -> io::Result<StateLock> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).mode(0o600).open(path)?;
+        let operation = if exclusive { libc::LOCK_EX } else { libc::LOCK_SH };
+        if unsafe { libc::flock(file.as_raw_fd(), operation) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(StateLock { _file: file })
+    }
+}
+
+// Write to a temporary file of this process and rename it over the state file,
+// so readers never see half of it and a crash leaves the old one
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(format!(".{}", process::id()));
+    let tmp_file = PathBuf::from(tmp_name);
+    let mut file = File::create(&tmp_file)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp_file, path)
+}
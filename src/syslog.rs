@@ -0,0 +1,27 @@
+use std::{ffi::CString, sync::Once};
+
+// Errors go to syslog, on stderr they would be drawn over the greeter on the same tty
+static OPEN: Once = Once::new();
+
+fn log(priority: libc::c_int, message: &str) {
+    OPEN.call_once(|| unsafe {
+        libc::openlog(c"nclogin".as_ptr(), libc::LOG_PID, libc::LOG_AUTHPRIV);
+    });
+    // Messages with a NUL byte are cut there
+    let message = CString::new(message.split('\0').next().unwrap_or_default()).unwrap_or_default();
+    unsafe {
+        libc::syslog(priority, c"%s".as_ptr(), message.as_ptr());
+    }
+}
+
+pub fn error(message: &str) {
+    log(libc::LOG_ERR, message);
+}
+
+pub fn warning(message: &str) {
+    log(libc::LOG_WARNING, message);
+}
+
+pub fn info(message: &str) {
+    log(libc::LOG_INFO, message);
+}
@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, io, path::{Path, PathBuf}, time::{SystemTime, UNIX_EPOCH}};
+
+use crate::settings::LoginThrottle;
+use crate::state_lock::{self, StateLock};
+use crate::syslog;
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct FailureRecord {
+    failures: u32,
+    // Unix time until no login attempt is allowed
+    blocked_until: u64,
+}
+
+// Failure counters persisted in the state directory
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct ThrottleState {
+    #[serde(default)]
+    users: HashMap<String, FailureRecord>,
+    #[serde(default)]
+    ttys: HashMap<String, FailureRecord>,
+}
+
+// Delays login attempts after consecutive failures per user and per tty.
+// Every tty runs its own helper, so the state file is read and written under a lock on every check.
+pub struct Throttle {
+    enabled: bool,
+    base_delay: u64,
+    max_delay: u64,
+    lockout_threshold: u32,
+    lockout_duration: u64,
+    state_file: PathBuf,
+    lock_file: PathBuf,
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+impl Throttle {
+    pub fn new(settings: &LoginThrottle) -> Throttle {
+        let state_dir = Path::new(&settings.state_dir);
+        Throttle {
+            enabled: settings.throttle_enabled,
+            base_delay: settings.base_delay,
+            max_delay: settings.max_delay,
+            lockout_threshold: settings.lockout_threshold,
+            lockout_duration: settings.lockout_duration,
+            state_file: state_dir.join("throttle.toml"),
+            lock_file: state_dir.join("throttle.lock"),
+        }
+    }
+
+    fn read_state(&self) -> ThrottleState {
+        fs::read_to_string(&self.state_file)
+            .ok()
+            .and_then(|content| toml::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    fn write_state(&self, state: &ThrottleState) -> io::Result<()> {
+        let toml_string = toml::to_string(state)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Failed to serialize to TOML"))?;
+        state_lock::write_atomically(&self.state_file, toml_string.as_bytes())
+    }
+
+    // Read, change and write the state under the exclusive lock, `change` returns false if nothing changed
+    fn update(&self, change: impl FnOnce(&mut ThrottleState) -> bool) {
+        let result = StateLock::acquire(&self.lock_file, true).and_then(|_lock| {
+            let mut state = self.read_state();
+            if change(&mut state) {
+                self.write_state(&state)?;
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            syslog::error(&format!("Failed to save login throttle state: {}", e));
+        }
+    }
+
+    // Seconds until the user may try again on this tty, None if a login attempt is allowed
+    pub fn remaining(&self, username: &str, tty: &str) -> Option<u64> {
+        if !self.enabled {
+            return None;
+        }
+        let lock = StateLock::acquire(&self.lock_file, false);
+        if let Err(e) = &lock {
+            syslog::error(&format!("Failed to lock login throttle state: {}", e));
+        }
+        let state = self.read_state();
+        drop(lock);
+
+        let now = now();
+        let blocked_until = [state.users.get(username), state.ttys.get(tty)]
+            .into_iter()
+            .flatten()
+            .map(|record| record.blocked_until)
+            .max()?;
+        (blocked_until > now).then(|| blocked_until - now)
+    }
+
+    pub fn record_failure(&self, username: &str, tty: &str) {
+        if !self.enabled {
+            return;
+        }
+        let now = now();
+        // Failures spread over a long time don't add up to a lockout, a count starts over after a quiet period
+        let quiet_period = self.lockout_duration.max(self.max_delay);
+        self.update(|state| {
+            for record in [
+                state.users.entry(username.to_string()).or_default(),
+                state.ttys.entry(tty.to_string()).or_default(),
+            ] {
+                if record.blocked_until.saturating_add(quiet_period) < now {
+                    record.failures = 0;
+                }
+                record.failures += 1;
+                let delay = if self.lockout_threshold > 0 && record.failures >= self.lockout_threshold {
+                    self.lockout_duration
+                } else {
+                    // Exponential delay: base, 2 * base, 4 * base, ... up to max_delay
+                    let factor = 1u64.checked_shl(record.failures - 1).unwrap_or(u64::MAX);
+                    self.base_delay.saturating_mul(factor).min(self.max_delay)
+                };
+                record.blocked_until = now + delay;
+            }
+            true
+        });
+    }
+
+    // Only the user's own record is cleared, a login to another account must not lift the lock of the tty
+    pub fn record_success(&self, username: &str) {
+        self.update(|state| state.users.remove(username).is_some());
+    }
+}
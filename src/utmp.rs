@@ -0,0 +1,343 @@
+use std::{env, ffi::CString, fs, io, mem::zeroed, process, time::{SystemTime, UNIX_EPOCH}};
+use libc::{c_char, c_short, endutxent, getutxent, pututxline, setutxent, utmpx, utmpxname};
+
+extern "C" {
+    // Appends an entry to a wtmp style file, provided by glibc but not exported by the libc crate
+    fn updwtmpx(wtmpx_file: *const c_char, utmpx: *const utmpx);
+}
+
+// Value of ut_type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordType {
+    Empty,
+    RunLevel,
+    BootTime,
+    NewTime,
+    OldTime,
+    InitProcess,
+    LoginProcess,
+    UserProcess,
+    DeadProcess,
+    Accounting,
+}
+
+impl RecordType {
+    fn from_raw(ut_type: c_short) -> RecordType {
+        match ut_type {
+            1 => RecordType::RunLevel,
+            2 => RecordType::BootTime,
+            3 => RecordType::NewTime,
+            4 => RecordType::OldTime,
+            5 => RecordType::InitProcess,
+            6 => RecordType::LoginProcess,
+            7 => RecordType::UserProcess,
+            8 => RecordType::DeadProcess,
+            9 => RecordType::Accounting,
+            _ => RecordType::Empty,
+        }
+    }
+
+    fn to_raw(self) -> c_short {
+        match self {
+            RecordType::Empty => 0,
+            RecordType::RunLevel => 1,
+            RecordType::BootTime => 2,
+            RecordType::NewTime => 3,
+            RecordType::OldTime => 4,
+            RecordType::InitProcess => 5,
+            RecordType::LoginProcess => 6,
+            RecordType::UserProcess => 7,
+            RecordType::DeadProcess => 8,
+            RecordType::Accounting => 9,
+        }
+    }
+}
+
+// Typed utmpx entry, strings longer than their field are truncated when written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UtmpRecord {
+    pub record_type: RecordType,
+    pub pid: i32,
+    // Device name without /dev/ (e.g. tty1)
+    pub line: String,
+    // Identifies the entry of a tty, at most 4 bytes
+    pub id: String,
+    pub user: String,
+    pub host: String,
+    pub session: i64,
+    pub tv_sec: i64,
+    pub tv_usec: i64,
+}
+
+// ut_id of a tty the way agetty sets it: the line without its "tty" prefix (tty1 -> "1", ttyS1 -> "S1"),
+// otherwise the last 4 bytes of the line (pts/12 -> "s/12"), cut to the 4 bytes of the field
+fn id_for_line(line: &str) -> String {
+    let id = match line.strip_prefix("tty") {
+        Some(rest) => &rest.as_bytes()[..rest.len().min(4)],
+        None => &line.as_bytes()[line.len().saturating_sub(4)..],
+    };
+    String::from_utf8_lossy(id).into_owned()
+}
+
+impl UtmpRecord {
+    // Record of a tty (e.g. /dev/tty1) at the current time, ut_line and ut_id are derived from the tty
+    pub fn new(record_type: RecordType, tty: &str) -> UtmpRecord {
+        let line = tty.trim_start_matches("/dev/").to_string();
+        UtmpRecord {
+            record_type,
+            pid: 0,
+            id: id_for_line(&line),
+            line,
+            user: String::new(),
+            host: String::new(),
+            session: 0,
+            tv_sec: 0,
+            tv_usec: 0,
+        }
+        .time(SystemTime::now())
+    }
+
+    pub fn user(mut self, user: &str) -> UtmpRecord {
+        self.user = user.to_string();
+        self
+    }
+
+    // Remote host, empty for local logins
+    pub fn host(mut self, host: &str) -> UtmpRecord {
+        self.host = host.to_string();
+        self
+    }
+
+    // Process of the login, the session id is taken from it
+    pub fn pid(mut self, pid: i32) -> UtmpRecord {
+        self.pid = pid;
+        let session = unsafe { libc::getsid(pid) };
+        self.session = if session > 0 { session as i64 } else { 0 };
+        self
+    }
+
+    pub fn time(mut self, time: SystemTime) -> UtmpRecord {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        self.tv_sec = since_epoch.as_secs() as i64;
+        self.tv_usec = since_epoch.subsec_micros() as i64;
+        self
+    }
+
+    // Logout record of this entry: a dead process with the same line and id, user and host are cleared
+    pub fn logout(&self) -> UtmpRecord {
+        UtmpRecord {
+            record_type: RecordType::DeadProcess,
+            user: String::new(),
+            host: String::new(),
+            ..self.clone()
+        }
+        .time(SystemTime::now())
+    }
+
+    fn to_raw(&self) -> utmpx {
+        let mut entry: utmpx = unsafe { zeroed() };
+        entry.ut_type = self.record_type.to_raw();
+        entry.ut_pid = self.pid;
+        copy_to_field(&mut entry.ut_line, &self.line);
+        copy_to_field(&mut entry.ut_id, &self.id);
+        copy_to_field(&mut entry.ut_user, &self.user);
+        copy_to_field(&mut entry.ut_host, &self.host);
+        // The field sizes differ between architectures (32 bit on x86_64 for compatibility)
+        entry.ut_session = self.session as _;
+        entry.ut_tv.tv_sec = self.tv_sec as _;
+        entry.ut_tv.tv_usec = self.tv_usec as _;
+        entry
+    }
+
+    fn from_raw(entry: &utmpx) -> UtmpRecord {
+        UtmpRecord {
+            record_type: RecordType::from_raw(entry.ut_type),
+            pid: entry.ut_pid,
+            line: field_to_string(&entry.ut_line),
+            id: field_to_string(&entry.ut_id),
+            user: field_to_string(&entry.ut_user),
+            host: field_to_string(&entry.ut_host),
+            session: entry.ut_session as i64,
+            tv_sec: entry.ut_tv.tv_sec as i64,
+            tv_usec: entry.ut_tv.tv_usec as i64,
+        }
+    }
+}
+
+// Copy a string into a fixed size field, the field is only NUL terminated if the string is shorter
+fn copy_to_field(field: &mut [c_char], value: &str) {
+    for (dst, src) in field.iter_mut().zip(value.bytes()) {
+        *dst = src as c_char;
+    }
+}
+
+fn field_to_string(field: &[c_char]) -> String {
+    let bytes: Vec<u8> = field.iter().take_while(|c| **c != 0).map(|c| *c as u8).collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+// Session of a logged in user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoggedInUser {
+    pub user: String,
+    pub tty: String,
+    pub host: String,
+    // Unix time of the login
+    pub login_time: i64,
+    pub pid: i32,
+}
+
+fn process_alive(pid: i32) -> bool {
+    if pid <= 0 {
+        return false;
+    }
+    // Signal 0 only checks the process, EPERM means it exists but belongs to another user
+    let result = unsafe { libc::kill(pid, 0) };
+    result == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+// User processes in the utmp file that are still running, stale entries of crashed sessions are skipped
+pub fn logged_in_users(path: &str) -> io::Result<Vec<LoggedInUser>> {
+    Ok(iter_records(path)?
+        .filter(|record| record.record_type == RecordType::UserProcess && process_alive(record.pid))
+        .map(|record| LoggedInUser {
+            user: record.user,
+            tty: record.line,
+            host: record.host,
+            login_time: record.tv_sec,
+            pid: record.pid,
+        })
+        .collect())
+}
+
+fn open_file(path: &str) -> io::Result<()> {
+    let c_path = CString::new(path).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Path contains NUL"))?;
+    unsafe {
+        if utmpxname(c_path.as_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        setutxent();
+    }
+    Ok(())
+}
+
+// Iterator over the entries of a utmp file.
+// glibc keeps a single utmp file open per process, so only one iterator may exist at a time.
+pub struct Records {
+    _private: (),
+}
+
+impl Iterator for Records {
+    type Item = UtmpRecord;
+
+    fn next(&mut self) -> Option<UtmpRecord> {
+        unsafe { getutxent().as_ref().map(UtmpRecord::from_raw) }
+    }
+}
+
+impl Drop for Records {
+    fn drop(&mut self) {
+        unsafe {
+            endutxent();
+        }
+    }
+}
+
+pub fn iter_records(path: &str) -> io::Result<Records> {
+    // utmpxname doesn't check the file, glibc silently returns no entries for a missing one
+    fs::metadata(path)?;
+    open_file(path)?;
+    Ok(Records { _private: () })
+}
+
+pub fn read_records(path: &str) -> io::Result<Vec<UtmpRecord>> {
+    Ok(iter_records(path)?.collect())
+}
+
+// Add the record to a utmp file, replacing the entry with the same id
+pub fn write_record(path: &str, record: &UtmpRecord) -> io::Result<()> {
+    fs::metadata(path)?;
+    open_file(path)?;
+    let entry = record.to_raw();
+    let result = unsafe { pututxline(&entry) };
+    let error = io::Error::last_os_error();
+    unsafe {
+        endutxent();
+    }
+    if result.is_null() {
+        return Err(error);
+    }
+    Ok(())
+}
+
+// Append the record to a log file like wtmp or btmp
+pub fn append_record(path: &str, record: &UtmpRecord) -> io::Result<()> {
+    // updwtmpx doesn't report errors and doesn't create the file, login doesn't create it either
+    fs::metadata(path)?;
+    let c_path = CString::new(path).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Path contains NUL"))?;
+    let entry = record.to_raw();
+    unsafe {
+        updwtmpx(c_path.as_ptr(), &entry);
+    }
+    Ok(())
+}
+
+// Exercise the API against scratch files in the temp directory
+pub fn main() -> io::Result<()> {
+    let scratch_dir = env::temp_dir().join(format!("nclogin-utmp-{}", process::id()));
+    fs::create_dir_all(&scratch_dir)?;
+    let utmp_path = scratch_dir.join("utmp").to_string_lossy().into_owned();
+    let wtmp_path = scratch_dir.join("wtmp").to_string_lossy().into_owned();
+    fs::write(&utmp_path, b"")?;
+    fs::write(&wtmp_path, b"")?;
+
+    // ut_id follows agetty, so the login replaces the LOGIN_PROCESS entry of the tty
+    assert_eq!(UtmpRecord::new(RecordType::UserProcess, "/dev/tty1").id, "1");
+    assert_eq!(UtmpRecord::new(RecordType::UserProcess, "/dev/ttyS1").id, "S1");
+    assert_ne!(UtmpRecord::new(RecordType::UserProcess, "/dev/tty1").id, UtmpRecord::new(RecordType::UserProcess, "/dev/ttyS1").id);
+    assert_eq!(UtmpRecord::new(RecordType::UserProcess, "/dev/pts/12").id, "s/12");
+    assert_eq!(UtmpRecord::new(RecordType::UserProcess, "/dev/ttyS12345").id, "S123");
+    assert_eq!(UtmpRecord::new(RecordType::UserProcess, "/dev/console").id, "sole");
+
+    let login = UtmpRecord::new(RecordType::UserProcess, "/dev/tty7")
+        .user("alice")
+        .host("")
+        .pid(process::id() as i32);
+    write_record(&utmp_path, &login)?;
+    append_record(&wtmp_path, &login)?;
+    assert_eq!(read_records(&utmp_path)?, vec![login.clone()]);
+    println!("Login: {:?}", login);
+
+    // A second tty gets its own entry
+    let other = UtmpRecord::new(RecordType::UserProcess, "/dev/tty8").user("bob").pid(process::id() as i32);
+    write_record(&utmp_path, &other)?;
+    assert_eq!(read_records(&utmp_path)?.len(), 2);
+
+    // The logout replaces the entry with the same id
+    let logout = login.logout();
+    write_record(&utmp_path, &logout)?;
+    append_record(&wtmp_path, &logout)?;
+    let records = read_records(&utmp_path)?;
+    assert_eq!(records, vec![logout.clone(), other.clone()]);
+    println!("Logout: {:?}", logout);
+
+    // Only running user processes count as logged in
+    let logged_in: Vec<String> = logged_in_users(&utmp_path)?.into_iter().map(|user| user.user).collect();
+    assert_eq!(logged_in, vec!["bob".to_string()]);
+    let crashed = UtmpRecord::new(RecordType::UserProcess, "/dev/tty9").user("carol").pid(i32::MAX);
+    write_record(&utmp_path, &crashed)?;
+    assert_eq!(logged_in_users(&utmp_path)?.len(), 1);
+
+    // wtmp keeps both records
+    let history: Vec<RecordType> = iter_records(&wtmp_path)?.map(|record| record.record_type).collect();
+    assert_eq!(history, vec![RecordType::UserProcess, RecordType::DeadProcess]);
+
+    fs::remove_dir_all(&scratch_dir)?;
+    println!("All utmp checks passed");
+
+    match logged_in_users("/var/run/utmp") {
+        Ok(users) => println!("Logged in on this system: {:?}", users),
+        Err(e) => println!("Failed to read /var/run/utmp: {}", e),
+    }
+    Ok(())
+}
@@ -3,5 +3,9 @@ SCRIPTPATH="$( cd -- "$(dirname "$0")" >/dev/null 2>&1 ; pwd -P )"
 cd $SCRIPTPATH
 cargo run --bin user
 cargo run --bin session
+cargo run --bin desktop_entry
 cargo run --bin settings
-cargo run --bin issue_helpers
\ No newline at end of file
+cargo run --bin issue_helpers
+cargo run --bin utmp
+cargo run --bin mock_auth
+cargo run --bin environment